@@ -0,0 +1,298 @@
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const FILE_PREFIX: &str = "aiscatcher-";
+
+/// How captured packets are laid out on disk.
+#[derive(Debug, Clone)]
+pub struct CaptureOptions {
+    /// Directory the capture files are written to
+    pub dir: PathBuf,
+    /// Start a new file once this many bytes (before compression) have been written; zero
+    /// disables size-based rotation
+    pub rotate_bytes: u64,
+    /// Start a new file once the current one is this old; zero disables time-based rotation
+    pub rotate_interval: Duration,
+    /// Compress the files with gzip
+    pub gzip: bool,
+    /// How many capture files to keep, deleting the oldest; zero keeps them all
+    pub keep_files: usize,
+}
+
+/// The file currently being written to
+struct CaptureFile {
+    path: PathBuf,
+    writer: Box<dyn Write + Send>,
+    opened: DateTime<Utc>,
+    bytes: u64,
+}
+
+/// Records the raw body of every packet posted by AIS-catcher, one packet per line, so that
+/// the packets can be replayed or shared later.
+///
+/// Files are named after the time they were started (`aiscatcher-<UTC time>.jsonl`, with
+/// `.gz` when compressed), so they sort into the order they were written.
+pub struct Capture {
+    options: CaptureOptions,
+    current: Option<CaptureFile>,
+    /// The time in the name of the last file started, so that the next one sorts after it
+    last_stamp: Option<DateTime<Utc>>,
+}
+
+impl Capture {
+    /// Set up capturing into a directory, creating it if need be. No file is created until
+    /// the first packet arrives.
+    pub fn new(options: CaptureOptions) -> io::Result<Capture> {
+        std::fs::create_dir_all(&options.dir)?;
+        Ok(Capture {
+            options,
+            current: None,
+            last_stamp: None,
+        })
+    }
+
+    /// Append a packet body to the current capture file, rotating first if it's due.
+    pub fn record(&mut self, body: &[u8]) -> io::Result<()> {
+        let now = Utc::now();
+        if self.rotation_due(now) {
+            self.rotate(now)?;
+        }
+        let file = match self.current.as_mut() {
+            Some(file) => file,
+            None => {
+                let file = self.open(now)?;
+                self.current.insert(file)
+            }
+        };
+        // JSON doesn't need the line breaks a pretty-printed body might have, and they
+        // would split the packet over several lines.
+        let mut line: Vec<u8> = body
+            .iter()
+            .map(|&b| if b == b'\n' || b == b'\r' { b' ' } else { b })
+            .collect();
+        line.push(b'\n');
+        file.writer.write_all(&line)?;
+        file.writer.flush()?;
+        file.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotation_due(&self, now: DateTime<Utc>) -> bool {
+        let Some(file) = &self.current else {
+            return false;
+        };
+        let too_big = self.options.rotate_bytes > 0 && file.bytes >= self.options.rotate_bytes;
+        let too_old = !self.options.rotate_interval.is_zero()
+            && now
+                .signed_duration_since(file.opened)
+                .to_std()
+                .unwrap_or_default()
+                >= self.options.rotate_interval;
+        too_big || too_old
+    }
+
+    /// Close the current file; the next packet starts a new one.
+    pub fn close(&mut self) {
+        if let Some(file) = self.current.take() {
+            // Dropping the writer finishes the gzip stream.
+            drop(file.writer);
+            tracing::info!("Closed capture file {}", file.path.display());
+        }
+    }
+
+    /// Start a new file, and delete the oldest files beyond the retention limit.
+    fn rotate(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        self.close();
+        self.current = Some(self.open(now)?);
+        self.prune()
+    }
+
+    fn open(&mut self, now: DateTime<Utc>) -> io::Result<CaptureFile> {
+        let stamp = match self.last_stamp {
+            Some(last) if last >= now => last + chrono::Duration::milliseconds(1),
+            _ => now,
+        };
+        let file = open_file(&self.options, stamp)?;
+        self.last_stamp = Some(file.opened);
+        Ok(file)
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        if self.options.keep_files == 0 {
+            return Ok(());
+        }
+        let mut files = capture_files(&self.options.dir)?;
+        if files.len() > self.options.keep_files {
+            let excess = files.len() - self.options.keep_files;
+            for path in files.drain(..excess) {
+                tracing::info!("Removing old capture file {}", path.display());
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The capture files in a directory, oldest first.
+pub fn capture_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_capture = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.starts_with(FILE_PREFIX)
+                    && (name.ends_with(".jsonl") || name.ends_with(".jsonl.gz"))
+            });
+        if is_capture {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn open_file(options: &CaptureOptions, now: DateTime<Utc>) -> io::Result<CaptureFile> {
+    let extension = if options.gzip { "jsonl.gz" } else { "jsonl" };
+    // Only likely when rotating on a tiny size limit, but never overwrite a capture. Moving
+    // the name on a millisecond at a time keeps the files sorting in the order written.
+    let mut stamp = now;
+    let mut path;
+    loop {
+        path = options.dir.join(format!(
+            "{}{}.{}",
+            FILE_PREFIX,
+            stamp.format("%Y%m%dT%H%M%S%.3fZ"),
+            extension
+        ));
+        if !path.exists() {
+            break;
+        }
+        stamp += chrono::Duration::milliseconds(1);
+    }
+    let file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(&path)?;
+    tracing::info!("Capturing packets to {}", path.display());
+    let writer: Box<dyn Write + Send> = if options.gzip {
+        Box::new(GzEncoder::new(BufWriter::new(file), Compression::default()))
+    } else {
+        Box::new(BufWriter::new(file))
+    };
+    Ok(CaptureFile {
+        path,
+        writer,
+        opened: stamp,
+        bytes: 0,
+    })
+}
+
+/// Open a capture file for reading, decompressing it if it was gzipped.
+pub fn open_capture_file(path: &Path) -> io::Result<Box<dyn io::Read>> {
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        Ok(Box::new(flate2::read::MultiGzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::io::Read;
+
+    fn options(dir: &TempDir) -> CaptureOptions {
+        CaptureOptions {
+            dir: dir.join("capture"),
+            rotate_bytes: 0,
+            rotate_interval: Duration::ZERO,
+            gzip: false,
+            keep_files: 0,
+        }
+    }
+
+    /// Record the packets `{"packet": 1}` and on up to `count`, pretty-printed
+    fn record_packets(options: CaptureOptions, count: usize) {
+        let mut capture = Capture::new(options).unwrap();
+        for n in 1..=count {
+            capture
+                .record(format!("{{\"packet\":\n{}}}", n).as_bytes())
+                .unwrap();
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        let mut contents = String::new();
+        open_capture_file(path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_capture_writes_a_line_per_packet() {
+        let dir = TempDir::new("capture-lines");
+        record_packets(options(&dir), 2);
+        let files = capture_files(&dir.join("capture")).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_str().unwrap().ends_with(".jsonl"));
+        assert_eq!(read(&files[0]), "{\"packet\": 1}\n{\"packet\": 2}\n");
+    }
+
+    #[test]
+    fn test_capture_rotates_on_size() {
+        let dir = TempDir::new("capture-rotate");
+        record_packets(
+            CaptureOptions {
+                rotate_bytes: 10,
+                ..options(&dir)
+            },
+            3,
+        );
+        let files = capture_files(&dir.join("capture")).unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(read(&files[2]), "{\"packet\": 3}\n");
+    }
+
+    #[test]
+    fn test_capture_keeps_the_newest_files() {
+        let dir = TempDir::new("capture-keep");
+        record_packets(
+            CaptureOptions {
+                rotate_bytes: 10,
+                keep_files: 2,
+                ..options(&dir)
+            },
+            4,
+        );
+        let files = capture_files(&dir.join("capture")).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(read(&files[0]), "{\"packet\": 3}\n");
+        assert_eq!(read(&files[1]), "{\"packet\": 4}\n");
+    }
+
+    #[test]
+    fn test_capture_gzip_reads_back() {
+        let dir = TempDir::new("capture-gzip");
+        record_packets(
+            CaptureOptions {
+                gzip: true,
+                ..options(&dir)
+            },
+            2,
+        );
+        let files = capture_files(&dir.join("capture")).unwrap();
+        assert!(files[0].to_str().unwrap().ends_with(".jsonl.gz"));
+        assert_eq!(read(&files[0]), "{\"packet\": 1}\n{\"packet\": 2}\n");
+    }
+}
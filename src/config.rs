@@ -0,0 +1,92 @@
+use crate::AppConfig;
+use std::path::Path;
+
+/// The formats a configuration file given by path can be written in, told apart by the
+/// file's extension. Files found by name in the platform's configuration directory are
+/// always TOML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Result<ConfigFormat, String> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => Err(format!(
+                "{} should end in .toml, .yaml, .yml or .json to say what format it's in",
+                path.display()
+            )),
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> Result<AppConfig, String> {
+        match self {
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_norway::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn render(&self, config: &AppConfig) -> Result<String, String> {
+        match self {
+            ConfigFormat::Toml => toml::to_string_pretty(config).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_norway::to_string(config).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::to_string_pretty(config).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Read a configuration file in whichever format its extension says.
+pub fn load_config_path(path: &Path) -> Result<AppConfig, String> {
+    let format = ConfigFormat::from_path(path)?;
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    format.parse(&text)
+}
+
+/// Write a configuration file in whichever format its extension says.
+pub fn store_config_path(path: &Path, config: &AppConfig) -> Result<(), String> {
+    let text = ConfigFormat::from_path(path)?.render(config)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_config_round_trips_in_each_format() {
+        let dir = TempDir::new("config-formats");
+        let mut config = AppConfig::default();
+        config.mmsi_lookup[0].station_name = "Dublin Bay, North".to_string();
+        for name in ["feeder.toml", "feeder.yaml", "feeder.json"] {
+            let path = dir.join(name);
+            store_config_path(&path, &config).unwrap();
+            let loaded = load_config_path(&path).unwrap();
+            assert_eq!(loaded.mmsi_lookup[0].station_name, "Dublin Bay, North");
+            assert_eq!(loaded.rename_fields, config.rename_fields);
+            assert_eq!(loaded.message_config[0].dac, Some(200));
+            assert_eq!(loaded.message_config[0].publish_fields, None);
+        }
+    }
+
+    #[test]
+    fn test_config_rejects_other_formats() {
+        let dir = TempDir::new("config-ini");
+        std::fs::write(dir.join("feeder.ini"), "").unwrap();
+        assert!(load_config_path(&dir.join("feeder.ini")).is_err());
+        assert!(store_config_path(&dir.join("feeder.ini"), &AppConfig::default()).is_err());
+    }
+}
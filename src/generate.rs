@@ -0,0 +1,324 @@
+use crate::{FieldSentinel, FieldSpec, FieldType, NotAvailableMode};
+
+/// The placeholder row written to init.jsonl uses these for the station columns, so it's
+/// easy to spot (and delete) once real data has arrived.
+const INIT_TIME: &str = "1970-01-01T00:00:00Z";
+const INIT_MMSI: u64 = 999999999;
+const INIT_STATION_NAME: &str = "Initial_Data";
+const INIT_AUTHOR: &str = "INITRECORD";
+
+/// A variable of a generated dataset: the name ERDDAP sees, what the decoder says about the
+/// field, and the value standing in for missing data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedVariable {
+    pub name: String,
+    pub spec: FieldSpec,
+    pub missing_value: Option<f64>,
+}
+
+impl GeneratedVariable {
+    /// Work out the missing value for a field from its not available code and the mode the
+    /// feeder runs in. With `missing`, ERDDAP's NaN default only needs stating for floating
+    /// point types; integer types already use their own default.
+    pub fn new(
+        name: String,
+        spec: FieldSpec,
+        sentinel: Option<&FieldSentinel>,
+        mode: NotAvailableMode,
+    ) -> GeneratedVariable {
+        let missing_value = match (mode, sentinel) {
+            (NotAvailableMode::Sentinel, Some(sentinel)) => Some(sentinel.not_available),
+            (NotAvailableMode::Missing, Some(_))
+                if matches!(spec.data_type, FieldType::Float | FieldType::Double) =>
+            {
+                Some(f64::NAN)
+            }
+            _ => None,
+        };
+        GeneratedVariable {
+            name,
+            spec,
+            missing_value,
+        }
+    }
+}
+
+/// An EDDTableFromHttpGet dataset matching what the feeder sends to one tabledap URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedDataset {
+    pub dataset_id: String,
+    pub variables: Vec<GeneratedVariable>,
+}
+
+impl GeneratedDataset {
+    /// The `<dataset>` element for datasets.xml. Attributes that only the ERDDAP
+    /// administrator can know, such as the title and the author keys, are left as
+    /// placeholders.
+    pub fn dataset_xml(&self) -> String {
+        let mut xml = format!(
+            r#"    <dataset type="EDDTableFromHttpGet" datasetID="{id}" active="true">
+        <reloadEveryNMinutes>20</reloadEveryNMinutes>
+        <updateEveryNMillis>-1</updateEveryNMillis>
+        <fileDir>/erddapData/sources/{id}/</fileDir>
+        <fileNameRegex>.*\.jsonl</fileNameRegex>
+        <recursive>true</recursive>
+        <pathRegex>.*</pathRegex>
+        <metadataFrom>last</metadataFrom>
+        <sortedColumnSourceName></sortedColumnSourceName>
+        <sortFilesBySourceNames>mmsi</sortFilesBySourceNames>
+        <fileTableInMemory>false</fileTableInMemory>
+        <accessibleViaFiles>true</accessibleViaFiles>
+        <addAttributes>
+            <att name="cdm_data_type">TimeSeries</att>
+            <att name="cdm_timeseries_variables">station_name</att>
+            <att name="Conventions">COARDS, CF-1.10, ACDD-1.3</att>
+            <att name="creator_name">YOUR NAME HERE</att>
+            <att name="creator_url">YOUR URL HERE</att>
+            <att name="httpGetDirectoryStructure">mmsi/10years</att>
+            <att name="httpGetKeys">ERDDAP_KEYS_HERE</att>
+            <att name="httpGetRequiredVariables">mmsi,station_name,time</att>
+            <att name="infoUrl">YOUR_INFO_URL_HERE</att>
+            <att name="institution">YOUR_INSTITUTION_HERE</att>
+            <att name="license">[standard]</att>
+            <att name="sourceUrl">(local files)</att>
+            <att name="standard_name_vocabulary">CF Standard Name Table v70</att>
+            <att name="subsetVariables">mmsi, station_name</att>
+            <att name="summary">SUMMARY_OF_DATASET_HERE</att>
+            <att name="testOutOfDate">now-1day</att>
+            <att name="title">TITLE_OF_DATASET_HERE</att>
+        </addAttributes>
+"#,
+            id = escape(&self.dataset_id)
+        );
+        for variable in &self.variables {
+            xml.push_str(&variable_xml(variable));
+        }
+        xml.push_str(ERDDAP_MANAGED_VARIABLES_XML);
+        xml.push_str("    </dataset>\n");
+        xml
+    }
+
+    /// The contents of init.jsonl: the column names, then a placeholder row that gives
+    /// ERDDAP a file to read before the first insert.
+    pub fn init_jsonl(&self) -> String {
+        let mut names: Vec<&str> = self.variables.iter().map(|v| v.name.as_str()).collect();
+        names.extend(["timestamp", "author", "command"]);
+        let mut values: Vec<serde_json::Value> = self.variables.iter().map(init_value).collect();
+        values.extend([0.into(), INIT_AUTHOR.into(), 0.into()]);
+        format!(
+            "{}\n{}\n",
+            serde_json::to_string(&names).unwrap(),
+            serde_json::to_string(&values).unwrap()
+        )
+    }
+}
+
+/// A complete datasets.xml holding each of the datasets.
+pub fn datasets_xml(datasets: &[GeneratedDataset]) -> String {
+    let mut xml =
+        String::from("<?xml version=\"1.0\" encoding=\"ISO-8859-1\" ?>\n<erddapDatasets>\n");
+    for dataset in datasets {
+        xml.push_str(&dataset.dataset_xml());
+    }
+    xml.push_str("</erddapDatasets>\n");
+    xml
+}
+
+fn variable_xml(variable: &GeneratedVariable) -> String {
+    let spec = &variable.spec;
+    let mut attributes = vec![
+        att("ioos_category", None, spec.ioos_category),
+        att("long_name", None, spec.long_name),
+    ];
+    if variable.name == "station_name" {
+        attributes.push(att("cf_role", None, "timeseries_id"));
+    }
+    if let Some(missing_value) = variable.missing_value {
+        let type_name = spec.data_type.to_string();
+        attributes.push(att(
+            "missing_value",
+            Some(&type_name),
+            &format_number(missing_value, spec.data_type),
+        ));
+    }
+    if let Some(standard_name) = spec.standard_name {
+        attributes.push(att("standard_name", None, standard_name));
+    }
+    if let Some(units) = spec.units {
+        attributes.push(att("units", None, units));
+    }
+    format!(
+        "        <dataVariable>
+            <sourceName>{name}</sourceName>
+            <destinationName>{name}</destinationName>
+            <dataType>{data_type}</dataType>
+            <addAttributes>
+{attributes}            </addAttributes>
+        </dataVariable>
+",
+        name = escape(&variable.name),
+        data_type = spec.data_type,
+        attributes = attributes.concat()
+    )
+}
+
+fn att(name: &str, data_type: Option<&str>, value: &str) -> String {
+    let data_type = data_type
+        .map(|t| format!(" type=\"{}\"", t))
+        .unwrap_or_default();
+    format!(
+        "                <att name=\"{}\"{}>{}</att>\n",
+        name,
+        data_type,
+        escape(value)
+    )
+}
+
+/// Format a number the way the column type would hold it, so integer codes aren't written
+/// with a decimal point.
+fn format_number(value: f64, data_type: FieldType) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if matches!(data_type, FieldType::Float | FieldType::Double) {
+        value.to_string()
+    } else {
+        format!("{:.0}", value)
+    }
+}
+
+fn init_value(variable: &GeneratedVariable) -> serde_json::Value {
+    match variable.name.as_str() {
+        "time" => return INIT_TIME.into(),
+        "mmsi" => return INIT_MMSI.into(),
+        "station_name" => return INIT_STATION_NAME.into(),
+        _ => {}
+    }
+    match (variable.spec.data_type, variable.missing_value) {
+        (FieldType::String, _) => "".into(),
+        (_, Some(value)) if !value.is_nan() => {
+            if matches!(
+                variable.spec.data_type,
+                FieldType::Float | FieldType::Double
+            ) {
+                value.into()
+            } else {
+                (value as i64).into()
+            }
+        }
+        _ => serde_json::Value::Null,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// The variables ERDDAP itself fills in for every EDDTableFromHttpGet dataset.
+const ERDDAP_MANAGED_VARIABLES_XML: &str = r#"        <dataVariable>
+            <sourceName>timestamp</sourceName>
+            <destinationName>timestamp</destinationName>
+            <dataType>double</dataType>
+            <addAttributes>
+                <att name="comment">The values in this column are added by ERDDAP to identify when each row of data was added to the data file.</att>
+                <att name="ioos_category">Time</att>
+                <att name="long_name">Timestamp</att>
+                <att name="missing_value" type="double">NaN</att>
+                <att name="time_precision">1970-01-01T00:00:00.000Z</att>
+                <att name="units">seconds since 1970-01-01T00:00:00Z</att>
+            </addAttributes>
+        </dataVariable>
+        <dataVariable>
+            <sourceName>author</sourceName>
+            <destinationName>author</destinationName>
+            <dataType>String</dataType>
+            <addAttributes>
+                <att name="comment">The values in this column identify the author who added each row of data to the dataset.</att>
+                <att name="ioos_category">Unknown</att>
+                <att name="long_name">Author</att>
+            </addAttributes>
+        </dataVariable>
+        <dataVariable>
+            <sourceName>command</sourceName>
+            <destinationName>command</destinationName>
+            <dataType>byte</dataType>
+            <addAttributes>
+                <att name="comment">Flag indicating insertion (0) or deletion (1) of records, due to the backing format being a log file.</att>
+                <att name="flag_meanings">insert delete</att>
+                <att name="flag_values" type="byteList">0 1</att>
+                <att name="ioos_category">Unknown</att>
+                <att name="long_name">Command</att>
+                <att name="missing_value" type="byte">127</att>
+            </addAttributes>
+        </dataVariable>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{STATION_FIELDS, TYPE8_DAC200_FID31_FIELDS, TYPE8_DAC200_FID31_SENTINELS};
+
+    #[test]
+    fn test_generated_dataset_matches_renamed_fields() {
+        let field = |name: &str| {
+            *TYPE8_DAC200_FID31_FIELDS
+                .iter()
+                .find(|f| f.name == name)
+                .unwrap()
+        };
+        let sentinel = |name: &str| {
+            TYPE8_DAC200_FID31_SENTINELS
+                .iter()
+                .find(|s| s.field == name)
+        };
+        let mut variables: Vec<GeneratedVariable> = STATION_FIELDS
+            .iter()
+            .map(|f| {
+                GeneratedVariable::new(f.name.to_string(), *f, None, NotAvailableMode::Sentinel)
+            })
+            .collect();
+        variables.push(GeneratedVariable::new(
+            "latitude".to_string(),
+            field("lat"),
+            sentinel("lat"),
+            NotAvailableMode::Sentinel,
+        ));
+        variables.push(GeneratedVariable::new(
+            "wspeed".to_string(),
+            field("wspeed"),
+            sentinel("wspeed"),
+            NotAvailableMode::Sentinel,
+        ));
+        variables.push(GeneratedVariable::new(
+            "airtemp".to_string(),
+            field("airtemp"),
+            sentinel("airtemp"),
+            NotAvailableMode::Missing,
+        ));
+        let dataset = GeneratedDataset {
+            dataset_id: "ais_weather_data".to_string(),
+            variables,
+        };
+
+        let xml = datasets_xml(std::slice::from_ref(&dataset));
+        assert!(xml.contains(r#"datasetID="ais_weather_data""#));
+        assert!(xml.contains("<sourceName>latitude</sourceName>"));
+        assert!(xml.contains(r#"<att name="missing_value" type="double">91</att>"#));
+        assert!(xml.contains(r#"<att name="missing_value" type="byte">127</att>"#));
+        assert!(xml.contains(r#"<att name="missing_value" type="float">NaN</att>"#));
+        assert!(xml.contains("<att name=\"units\">yyyy-MM-dd&#39;T&#39;HH:mm:ss&#39;Z&#39;</att>"));
+
+        assert_eq!(
+            dataset.init_jsonl(),
+            concat!(
+                r#"["time","station_name","mmsi","latitude","wspeed","airtemp","timestamp","author","command"]"#,
+                "\n",
+                r#"["1970-01-01T00:00:00Z","Initial_Data",999999999,91.0,127,null,0,"INITRECORD",0]"#,
+                "\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Something that happened while talking to ERDDAP.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ErddapEvent {
+    pub at: DateTime<Utc>,
+    /// The sink the ERDDAP dataset belongs to
+    pub sink: String,
+    /// What was being attempted - "insert" for rows, "probe" for the .das check
+    pub source: &'static str,
+    /// The ERDDAP dataset URL, not including .insert or .das
+    pub url: String,
+    pub detail: String,
+}
+
+/// The most recent successful and failed exchanges with one sink's ERDDAP service.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SinkHealth {
+    pub last_success: Option<ErddapEvent>,
+    pub last_failure: Option<ErddapEvent>,
+}
+
+impl SinkHealth {
+    /// Why the sink isn't ready, if it isn't: the most recent exchange failed, or, with a
+    /// non-zero `window`, nothing has succeeded within that window of `now`, counting from
+    /// `started` until the first success.
+    fn unready_reason(
+        &self,
+        window: Duration,
+        started: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<String> {
+        let last_success_at = self.last_success.as_ref().map(|e| e.at);
+        match &self.last_failure {
+            Some(failure) if last_success_at.is_none_or(|at| at < failure.at) => Some(format!(
+                "last ERDDAP {} failed: {}",
+                failure.source, failure.detail
+            )),
+            _ if !window.is_zero() => {
+                let since = last_success_at.unwrap_or(started);
+                let age = now
+                    .signed_duration_since(since)
+                    .to_std()
+                    .unwrap_or_default();
+                (age > window).then(|| {
+                    format!(
+                        "no successful ERDDAP exchange in the last {}s",
+                        window.as_secs()
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The most recent successful and failed exchanges with ERDDAP, overall and for each sink,
+/// and whether the configuration file last loaded, used to decide whether the feeder is
+/// ready.
+#[derive(Debug, Clone)]
+pub struct ErddapHealth {
+    pub started: DateTime<Utc>,
+    pub last_success: Option<ErddapEvent>,
+    pub last_failure: Option<ErddapEvent>,
+    /// Every configured sink, whether or not it's been sent anything yet
+    pub sinks: BTreeMap<String, SinkHealth>,
+    /// Why the configuration file couldn't be reloaded, if the last attempt failed
+    pub config_error: Option<String>,
+}
+
+/// The body of the /readyz response.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub config_loaded: bool,
+    /// Why the feeder isn't ready, if it isn't
+    pub reason: Option<String>,
+    pub ready_window_seconds: Option<u64>,
+    pub last_success: Option<ErddapEvent>,
+    pub last_failure: Option<ErddapEvent>,
+    pub sinks: BTreeMap<String, SinkHealth>,
+}
+
+impl ErddapHealth {
+    pub fn new() -> ErddapHealth {
+        ErddapHealth {
+            started: Utc::now(),
+            last_success: None,
+            last_failure: None,
+            sinks: BTreeMap::new(),
+            config_error: None,
+        }
+    }
+
+    pub fn record_success(&mut self, sink: &str, source: &'static str, url: &str, detail: String) {
+        let event = event(sink, source, url, detail);
+        self.sinks.entry(sink.to_string()).or_default().last_success = Some(event.clone());
+        self.last_success = Some(event);
+    }
+
+    pub fn record_failure(&mut self, sink: &str, source: &'static str, url: &str, detail: String) {
+        let event = event(sink, source, url, detail);
+        self.sinks.entry(sink.to_string()).or_default().last_failure = Some(event.clone());
+        self.last_failure = Some(event);
+    }
+
+    /// Follow the configured sinks: forget the ones that have been removed, and watch new
+    /// ones from now on, so one that's never answered still counts against the window.
+    pub fn set_sinks(&mut self, sinks: &[&str]) {
+        self.sinks.retain(|name, _| sinks.contains(&name.as_str()));
+        for sink in sinks {
+            self.sinks.entry(sink.to_string()).or_default();
+        }
+    }
+
+    /// Note the outcome of loading the configuration file: None if it loaded, and otherwise
+    /// why not. The feeder carries on with its previous configuration, but isn't ready.
+    pub fn record_config_load(&mut self, error: Option<String>) {
+        self.config_error = error;
+    }
+
+    /// Work out whether the feeder is ready. It isn't if the configuration file failed to
+    /// load, if the most recent exchange with any sink failed, or, with a non-zero `window`,
+    /// if any sink hasn't had a success within that window. The window starts at process
+    /// start, so a freshly started feeder is ready until it has had a chance to talk to
+    /// ERDDAP.
+    pub fn readiness(&self, window: Duration, now: DateTime<Utc>) -> Readiness {
+        let reason = if let Some(error) = &self.config_error {
+            Some(format!(
+                "configuration could not be reloaded, so the previous one is still in use: {}",
+                error
+            ))
+        } else if self.sinks.is_empty() {
+            SinkHealth::default().unready_reason(window, self.started, now)
+        } else {
+            let reasons: Vec<String> = self
+                .sinks
+                .iter()
+                .filter_map(|(name, sink)| {
+                    let reason = sink.unready_reason(window, self.started, now)?;
+                    Some(format!("{}: {}", name, reason))
+                })
+                .collect();
+            (!reasons.is_empty()).then(|| reasons.join("; "))
+        };
+        Readiness {
+            ready: reason.is_none(),
+            config_loaded: self.config_error.is_none(),
+            reason,
+            ready_window_seconds: (!window.is_zero()).then_some(window.as_secs()),
+            last_success: self.last_success.clone(),
+            last_failure: self.last_failure.clone(),
+            sinks: self.sinks.clone(),
+        }
+    }
+}
+
+impl Default for ErddapHealth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn event(sink: &str, source: &'static str, url: &str, detail: String) -> ErddapEvent {
+    ErddapEvent {
+        at: Utc::now(),
+        sink: sink.to_string(),
+        source,
+        url: url.to_string(),
+        detail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_follows_latest_outcome_and_window() {
+        let url = "https://erddap.example.com/erddap/tabledap/test";
+        let window = Duration::from_secs(60);
+        let mut health = ErddapHealth::new();
+        assert!(health.readiness(window, Utc::now()).ready);
+        // Nothing has happened since start, and the window has passed
+        let later = Utc::now() + chrono::Duration::seconds(120);
+        assert!(!health.readiness(window, later).ready);
+        assert!(health.readiness(Duration::ZERO, later).ready);
+
+        health.record_failure("default", "insert", url, "ERDDAP returned 503".to_string());
+        let readiness = health.readiness(window, Utc::now());
+        assert!(!readiness.ready);
+        assert!(readiness.reason.unwrap().contains("503"));
+
+        health.record_success("default", "probe", url, "200 OK".to_string());
+        let readiness = health.readiness(window, Utc::now());
+        assert!(readiness.ready);
+        assert!(readiness.last_failure.is_some());
+
+        // A later success from another sink doesn't hide a sink that's failing
+        health.record_failure("partner", "insert", url, "ERDDAP returned 502".to_string());
+        health.record_success("default", "insert", url, "success for 1 rows".to_string());
+        let readiness = health.readiness(window, Utc::now());
+        assert!(!readiness.ready);
+        assert!(readiness.reason.unwrap().starts_with("partner: "));
+        health.set_sinks(&["default"]);
+        assert!(health.readiness(window, Utc::now()).ready);
+    }
+
+    #[test]
+    fn test_readiness_counts_silent_sinks_and_the_configuration() {
+        let url = "https://erddap.example.com/erddap/tabledap/test";
+        let window = Duration::from_secs(60);
+        let mut health = ErddapHealth::new();
+        health.set_sinks(&["default", "partner"]);
+        health.record_success("default", "insert", url, "success for 1 rows".to_string());
+        assert!(health.readiness(window, Utc::now()).ready);
+        // The partner has never been heard from, and the window has passed since start
+        let later = Utc::now() + chrono::Duration::seconds(30);
+        health
+            .sinks
+            .get_mut("default")
+            .unwrap()
+            .last_success
+            .as_mut()
+            .unwrap()
+            .at = later;
+        let readiness = health.readiness(window, later + chrono::Duration::seconds(45));
+        assert!(!readiness.ready);
+        assert!(readiness
+            .reason
+            .unwrap()
+            .starts_with("partner: no successful"));
+
+        health.record_config_load(Some("Could not parse the configuration".to_string()));
+        let readiness = health.readiness(Duration::ZERO, Utc::now());
+        assert!(!readiness.ready);
+        assert!(!readiness.config_loaded);
+        health.record_config_load(None);
+        assert!(health.readiness(Duration::ZERO, Utc::now()).config_loaded);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{Column, ColumnValue};
+use chrono::{DateTime, FixedOffset};
+
+/// Format a decoded message as a point in InfluxDB line protocol, timestamped in seconds:
+/// `measurement,tag=value field=value 1696161600`. Tags with empty values are left out, as
+/// InfluxDB won't take them, as are fields that are missing or not finite. Without any
+/// fields there's no point to write, and None is returned.
+pub fn line_protocol(
+    measurement: &str,
+    tags: &[(&str, &str)],
+    fields: &[Column],
+    time: DateTime<FixedOffset>,
+) -> Option<String> {
+    let fields: Vec<String> = fields
+        .iter()
+        .filter_map(|column| {
+            let value = match &column.value {
+                ColumnValue::Float(value, _) if value.is_finite() => value.to_string(),
+                ColumnValue::Integer(value) => format!("{}i", value),
+                // Written as integers rather than InfluxDB's unsigned type, which not every
+                // client can query; the decoders' unsigned values are all small.
+                ColumnValue::Unsigned(value) => format!("{}i", value),
+                ColumnValue::Text(value) => {
+                    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+                }
+                ColumnValue::Float(..) | ColumnValue::Missing => return None,
+            };
+            Some(format!("{}={}", escape(&column.name, ",= "), value))
+        })
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+    let mut line = escape(measurement, ", ");
+    for (key, value) in tags.iter().filter(|(_, value)| !value.is_empty()) {
+        line.push_str(&format!(",{}={}", escape(key, ",= "), escape(value, ",= ")));
+    }
+    Some(format!(
+        "{} {} {}",
+        line,
+        fields.join(","),
+        time.timestamp()
+    ))
+}
+
+/// Backslash the characters that are special where a name or tag value appears.
+fn escape(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_protocol_escapes_and_types_values() {
+        let time = DateTime::parse_from_rfc3339("2023-10-01T12:00:00Z").unwrap();
+        let fields = vec![
+            Column::new("latitude", ColumnValue::Float(53.3, Some(3))),
+            Column::new("wspeed", ColumnValue::Unsigned(12)),
+            Column::new("airtemp", ColumnValue::Missing),
+            Column::new("waterlevel", ColumnValue::Float(f64::NAN, None)),
+            Column::new("turn", ColumnValue::Integer(-5)),
+            Column::new("remark", ColumnValue::Text("say \"hi\"".to_string())),
+        ];
+        let tags = [
+            ("mmsi", "992501234"),
+            ("station_name", "Dublin Bay, North"),
+            ("owner", ""),
+        ];
+        assert_eq!(
+            line_protocol("imo289_met_hydro", &tags, &fields, time).unwrap(),
+            "imo289_met_hydro,mmsi=992501234,station_name=Dublin\\ Bay\\,\\ North \
+             latitude=53.3,wspeed=12i,turn=-5i,remark=\"say \\\"hi\\\"\" 1696161600"
+        );
+        let missing = [Column::new("airtemp", ColumnValue::Missing)];
+        assert_eq!(
+            line_protocol("imo289_met_hydro", &tags, &missing, time),
+            None
+        );
+    }
+}
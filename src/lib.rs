@@ -6,9 +6,33 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub mod capture;
+pub mod config;
+pub mod generate;
+pub mod health;
+pub mod influx;
+pub mod metrics;
+pub mod output;
+pub mod registry;
+pub mod schema;
+pub mod spool;
+pub mod store;
+#[cfg(test)]
+mod testing;
+pub mod validate;
+
 pub const DEFAULT_MMSI: &str = "00000";
 pub const DEFAULT_URL: &str = "https://erddap.example.com/erddap/tabledap/data_set";
 pub const DEFAULT_KEY: &str = "username_password";
+/// The name of the sink made from the top level erddap_url and erddap_key settings
+pub const DEFAULT_SINK: &str = "default";
+
+/// The key/value pairs for one row of an ERDDAP .insert request
+pub type QueryRow = Vec<(String, String)>;
 
 #[derive(Deserialize, Debug)]
 /// Data about the AIS receiver software
@@ -66,7 +90,7 @@ pub struct AisCatcherMessage {
     pub msgs: Vec<AisMessage>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct AisStationData {
     /// The Mobile Marine Service Identifier - 9 digits. ATON will start 99.
     pub mmsi: u64,
@@ -77,21 +101,104 @@ pub struct AisStationData {
     pub rxtime: DateTime<FixedOffset>,
 }
 
+/// Problems found while converting an AIS-catcher message, naming the field at fault
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// A required field is not in the message
+    MissingField(String),
+    /// A field holds the wrong kind of JSON value, such as a string where a number should be
+    WrongType {
+        field: String,
+        expected: &'static str,
+        found: String,
+    },
+    /// A field holds the right kind of value, but it can't be used
+    InvalidValue {
+        field: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::MissingField(field) => write!(f, "field '{}' is missing", field),
+            DecodeError::WrongType {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "field '{}' should be {}, found {}",
+                field, expected, found
+            ),
+            DecodeError::InvalidValue {
+                field,
+                value,
+                reason,
+            } => write!(
+                f,
+                "field '{}' has unusable value {}: {}",
+                field, value, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl DecodeError {
+    fn wrong_type(field: &str, expected: &'static str, found: &serde_json::Value) -> Self {
+        DecodeError::WrongType {
+            field: field.to_string(),
+            expected,
+            found: found.to_string(),
+        }
+    }
+}
+
+/// Fetch a field that must be present in the message.
+fn required<'a>(
+    msg: &'a HashMap<String, serde_json::Value>,
+    field: &str,
+) -> Result<&'a serde_json::Value, DecodeError> {
+    msg.get(field)
+        .ok_or_else(|| DecodeError::MissingField(field.to_string()))
+}
+
 /// Extracts fields from the AisMessage structure, and produces an AisStationData structure
-impl From<&AisMessage> for AisStationData {
-    fn from(f: &AisMessage) -> Self {
+impl TryFrom<&AisMessage> for AisStationData {
+    type Error = DecodeError;
+
+    fn try_from(f: &AisMessage) -> Result<Self, Self::Error> {
         // Deal with the fact that the string rxtime is not in any known format for auto
         // conversion.
+        let rxtime = required(&f.msg, "rxtime")?;
+        let rxtime_str = rxtime
+            .as_str()
+            .ok_or_else(|| DecodeError::wrong_type("rxtime", "a string", rxtime))?;
         let chrono_ref =
-            NaiveDateTime::parse_from_str(f.msg["rxtime"].as_str().unwrap(), "%Y%m%d%H%M%S")
-                .unwrap();
+            NaiveDateTime::parse_from_str(rxtime_str, "%Y%m%d%H%M%S").map_err(|e| {
+                DecodeError::InvalidValue {
+                    field: "rxtime".to_string(),
+                    value: rxtime.to_string(),
+                    reason: e.to_string(),
+                }
+            })?;
         let tz_offset = FixedOffset::west_opt(0).unwrap();
-        let dt_ref: DateTime<FixedOffset> = tz_offset.from_local_datetime(&chrono_ref).unwrap();
-        AisStationData {
-            mmsi: f.msg["mmsi"].as_u64().unwrap(),
-            signal_power: f.msg["signalpower"].as_f64().unwrap(),
+        let dt_ref: DateTime<FixedOffset> = tz_offset.from_utc_datetime(&chrono_ref);
+        let mmsi = required(&f.msg, "mmsi")?;
+        let signal_power = required(&f.msg, "signalpower")?;
+        Ok(AisStationData {
+            mmsi: mmsi
+                .as_u64()
+                .ok_or_else(|| DecodeError::wrong_type("mmsi", "an unsigned integer", mmsi))?,
+            signal_power: signal_power
+                .as_f64()
+                .ok_or_else(|| DecodeError::wrong_type("signalpower", "a number", signal_power))?,
             rxtime: dt_ref,
-        }
+        })
     }
 }
 
@@ -123,6 +230,17 @@ impl AisStationData {
     }
 }
 
+/// The columns AisStationData adds to every row.
+pub const STATION_FIELDS: &[FieldSpec] = &[
+    FieldSpec::new("time", FieldType::String)
+        .describe("Time", "Time")
+        .units("yyyy-MM-dd'T'HH:mm:ss'Z'")
+        .standard_name("time"),
+    FieldSpec::new("station_name", FieldType::String).describe("Station Name", "Identifier"),
+    FieldSpec::new("mmsi", FieldType::UInt)
+        .describe("Maritime Mobile Service Identity", "Identifier"),
+];
+
 /// Structure to hold the data from an IMO289 weather packet, Type 8 DAC 200 FID 31.
 /// AIS Catcher provides scaled data.
 #[derive(Debug, Default)]
@@ -139,7 +257,7 @@ pub struct AisType8Dac200Fid31 {
     pub wdir: u64,
     /// Wind gust bearing in degrees true, 0-359, 360 = N/A
     pub wgustdir: u64,
-    /// Air temperature, dry bulb, -60 to +60 in 0.1C, -1024 = N/A
+    /// Air temperature, dry bulb, -60 to +60 in 0.1C, -1024 (-102.4 scaled) = N/A
     pub airtemp: f64,
     /// Dew point, -20 to +50 in 0.1C, 501 = N/A
     pub dewpoint: f64,
@@ -168,7 +286,9 @@ pub struct AisType8Dac200Fid31 {
     pub waveheight: f64,
     /// Wave period in seconds. 0 - 60. 63 = N/A
     pub waveperiod: u64,
+    /// Swell height in metres. 0 - 25m in 0.1. 251 = height >= 25.1. 255 = N/A
     pub swellheight: f64,
+    /// Swell period in seconds. 0 - 60. 63 = N/A
     pub swellperiod: u64,
     pub seastate: u64,
     pub watertemp: f64,
@@ -189,21 +309,26 @@ pub struct AisMessageIdentifier {
     pub fid: Option<u64>,
 }
 
-impl From<&AisMessage> for AisMessageIdentifier {
-    fn from(f: &AisMessage) -> Self {
-        let dac = match &f.msg.get("dac") {
-            Some(serde_json::Value::Number(n)) => Some(n.as_u64().unwrap()),
-            _ => None,
-        };
-        let fid = match &f.msg.get("fid") {
-            Some(serde_json::Value::Number(n)) => Some(n.as_u64().unwrap()),
-            _ => None,
+impl TryFrom<&AisMessage> for AisMessageIdentifier {
+    type Error = DecodeError;
+
+    fn try_from(f: &AisMessage) -> Result<Self, Self::Error> {
+        // DAC and FID only exist for binary messages.
+        let optional = |field: &str| match f.msg.get(field) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(value) => value
+                .as_u64()
+                .map(Some)
+                .ok_or_else(|| DecodeError::wrong_type(field, "an unsigned integer", value)),
         };
-        AisMessageIdentifier {
-            r#type: f.msg["type"].as_u64().unwrap(),
-            dac,
-            fid,
-        }
+        let r#type = required(&f.msg, "type")?;
+        Ok(AisMessageIdentifier {
+            r#type: r#type
+                .as_u64()
+                .ok_or_else(|| DecodeError::wrong_type("type", "an unsigned integer", r#type))?,
+            dac: optional("dac")?,
+            fid: optional("fid")?,
+        })
     }
 }
 
@@ -223,17 +348,28 @@ impl fmt::Display for AisMessageIdentifier {
 
 /// Load from an optional Value/Number from the named field, defaulting to the supplied
 /// value if the data was not present in the source JSON.
-fn load_f64(msg: &HashMap<String, serde_json::Value>, field: &str, default: f64) -> f64 {
+fn load_f64(
+    msg: &HashMap<String, serde_json::Value>,
+    field: &str,
+    default: f64,
+) -> Result<f64, DecodeError> {
     match msg.get(field) {
-        Some(n) => n.as_f64().unwrap(),
-        _ => default,
+        None | Some(serde_json::Value::Null) => Ok(default),
+        Some(n) => n
+            .as_f64()
+            .ok_or_else(|| DecodeError::wrong_type(field, "a number", n)),
     }
 }
 
 /// Load from an optional Value/Number from the named field, defaulting to the supplied
 /// value if the data was not present in the source JSON.
-fn load_u64(msg: &HashMap<String, serde_json::Value>, field: &str, default: u64) -> u64 {
+fn load_u64(
+    msg: &HashMap<String, serde_json::Value>,
+    field: &str,
+    default: u64,
+) -> Result<u64, DecodeError> {
     match msg.get(field) {
+        None | Some(serde_json::Value::Null) => Ok(default),
         // Be gracious in what's accepted. Perhaps the upstream sends a f64 value when the
         // spec details the field as u64. Trust that the significand is correct, and coerce
         // the floating poing value into an integer.
@@ -241,106 +377,861 @@ fn load_u64(msg: &HashMap<String, serde_json::Value>, field: &str, default: u64)
         Some(n) => n
             .as_u64()
             .or_else(|| n.as_f64().map(|f| f as u64))
-            .unwrap_or(default),
-        None => default,
+            .ok_or_else(|| DecodeError::wrong_type(field, "an unsigned integer", n)),
     }
 }
 
 /// Extracts fields from the AisMessage structure, and produces an AisType8Dac200Fid31 structure
-impl From<&AisMessage> for AisType8Dac200Fid31 {
-    fn from(f: &AisMessage) -> Self {
+impl TryFrom<&AisMessage> for AisType8Dac200Fid31 {
+    type Error = DecodeError;
+
+    fn try_from(f: &AisMessage) -> Result<Self, Self::Error> {
         // The default value is the *scaled* value from AIS Catcher, based on the details in
         // https://gpsd.gitlab.io/gpsd/AIVDM.html#_meteorological_and_hydrological_data_imo289
         // So, while the datastructure may document watertemp as 501 = N/A, the scaled value
         // is 50.1 (for example).
-        AisType8Dac200Fid31 {
-            airtemp: load_f64(&f.msg, "airtemp", -1024_f64),
-            cdepth2: load_u64(&f.msg, "cdepth2", 31),
-            cdepth3: load_u64(&f.msg, "cdepth3", 31),
-            cdir2: load_u64(&f.msg, "cdir2", 360),
-            cdir3: load_u64(&f.msg, "cdir3", 360),
-            cdir: load_u64(&f.msg, "cdir", 360),
-            cspeed2: load_f64(&f.msg, "cspeed2", 25.5),
-            cspeed3: load_f64(&f.msg, "cspeed3", 25.5),
-            cspeed: load_f64(&f.msg, "cspeed", 25.5),
-            dewpoint: load_f64(&f.msg, "dewpoint", 50.1),
-            ice: load_u64(&f.msg, "preciptype", 3),
-            lat: load_f64(&f.msg, "lat", 91.000),
-            leveltrend: load_u64(&f.msg, "leveltrend", 3),
-            lon: load_f64(&f.msg, "lon", 181.000),
-            preciptype: load_u64(&f.msg, "preciptype", 7),
-            pressure: load_u64(&f.msg, "pressure", 511),
-            pressuretend: load_u64(&f.msg, "pressuretend", 3),
-            salinity: load_f64(&f.msg, "salinity", 511_f64),
-            seastate: load_u64(&f.msg, "seastate", 13),
-            swellheight: load_f64(&f.msg, "swellheight", 25.5),
-            swellperiod: load_u64(&f.msg, "swellperiod", 360),
-            visgreater: load_f64(&f.msg, "visgreater", 1_f64),
-            visibility: load_f64(&f.msg, "visibility", 12.7),
-            waterlevel: load_f64(&f.msg, "waterlevel", 30.01),
-            watertemp: load_f64(&f.msg, "watertemp", 50.1),
-            waveheight: load_f64(&f.msg, "waveheight", 25.5),
-            waveperiod: load_u64(&f.msg, "waveperiod", 63),
-            wdir: load_u64(&f.msg, "wdir", 360),
-            wgust: load_u64(&f.msg, "wgust", 127),
-            wgustdir: load_u64(&f.msg, "wgustdir", 360),
-            wspeed: load_u64(&f.msg, "wspeed", 127),
-        }
+        Ok(AisType8Dac200Fid31 {
+            airtemp: load_f64(&f.msg, "airtemp", -102.4)?,
+            cdepth2: load_u64(&f.msg, "cdepth2", 31)?,
+            cdepth3: load_u64(&f.msg, "cdepth3", 31)?,
+            cdir2: load_u64(&f.msg, "cdir2", 360)?,
+            cdir3: load_u64(&f.msg, "cdir3", 360)?,
+            cdir: load_u64(&f.msg, "cdir", 360)?,
+            cspeed2: load_f64(&f.msg, "cspeed2", 25.5)?,
+            cspeed3: load_f64(&f.msg, "cspeed3", 25.5)?,
+            cspeed: load_f64(&f.msg, "cspeed", 25.5)?,
+            dewpoint: load_f64(&f.msg, "dewpoint", 50.1)?,
+            ice: load_u64(&f.msg, "preciptype", 3)?,
+            lat: load_f64(&f.msg, "lat", 91.000)?,
+            leveltrend: load_u64(&f.msg, "leveltrend", 3)?,
+            lon: load_f64(&f.msg, "lon", 181.000)?,
+            preciptype: load_u64(&f.msg, "preciptype", 7)?,
+            pressure: load_u64(&f.msg, "pressure", 511)?,
+            pressuretend: load_u64(&f.msg, "pressuretend", 3)?,
+            salinity: load_f64(&f.msg, "salinity", 51.1)?,
+            seastate: load_u64(&f.msg, "seastate", 13)?,
+            swellheight: load_f64(&f.msg, "swellheight", 25.5)?,
+            swellperiod: load_u64(&f.msg, "swellperiod", 63)?,
+            visgreater: load_f64(&f.msg, "visgreater", 1_f64)?,
+            visibility: load_f64(&f.msg, "visibility", 12.7)?,
+            waterlevel: load_f64(&f.msg, "waterlevel", 30.01)?,
+            watertemp: load_f64(&f.msg, "watertemp", 50.1)?,
+            waveheight: load_f64(&f.msg, "waveheight", 25.5)?,
+            waveperiod: load_u64(&f.msg, "waveperiod", 63)?,
+            wdir: load_u64(&f.msg, "wdir", 360)?,
+            wgust: load_u64(&f.msg, "wgust", 127)?,
+            wgustdir: load_u64(&f.msg, "wgustdir", 360)?,
+            wspeed: load_u64(&f.msg, "wspeed", 127)?,
+        })
     }
 }
 
-/// Converts an AisType8Dac200Fid31 into a set of key/value pairs that line up with what the ERDDAP
-/// system is configured to store.
+/// Converts an AisType8Dac200Fid31 into columns, and from there into a set of key/value pairs
+/// that line up with what the ERDDAP system is configured to store.
 impl AisType8Dac200Fid31 {
+    pub fn columns(&self) -> Vec<Column> {
+        vec![
+            Column::float("airtemp", self.airtemp),
+            Column::unsigned("cdepth2", self.cdepth2),
+            Column::unsigned("cdepth3", self.cdepth3),
+            Column::unsigned("cdir", self.cdir),
+            Column::unsigned("cdir2", self.cdir2),
+            Column::unsigned("cdir3", self.cdir3),
+            Column::float("cspeed", self.cspeed),
+            Column::float("cspeed2", self.cspeed2),
+            Column::float("cspeed3", self.cspeed3),
+            Column::float("dewpoint", self.dewpoint),
+            Column::unsigned("ice", self.ice),
+            Column::fixed("lat", self.lat, 3),
+            Column::unsigned("leveltrend", self.leveltrend),
+            Column::fixed("lon", self.lon, 3),
+            Column::unsigned("preciptype", self.preciptype),
+            Column::unsigned("pressure", self.pressure),
+            Column::unsigned("pressuretend", self.pressuretend),
+            Column::float("salinity", self.salinity),
+            Column::unsigned("seastate", self.seastate),
+            Column::float("swellheight", self.swellheight),
+            Column::unsigned("swellperiod", self.swellperiod),
+            Column::float("visgreater", self.visgreater),
+            Column::float("visibility", self.visibility),
+            Column::float("waterlevel", self.waterlevel),
+            Column::float("watertemp", self.watertemp),
+            Column::float("waveheight", self.waveheight),
+            Column::unsigned("waveperiod", self.waveperiod),
+            Column::unsigned("wdir", self.wdir),
+            Column::unsigned("wgust", self.wgust),
+            Column::unsigned("wgustdir", self.wgustdir),
+            Column::unsigned("wspeed", self.wspeed),
+        ]
+    }
+
     pub fn as_query_arguments(&self) -> Vec<(String, String)> {
-        let weather = vec![
-            ("airtemp", self.airtemp.to_string()),
-            ("cdepth2", self.cdepth2.to_string()),
-            ("cdepth3", self.cdepth3.to_string()),
-            ("cdir", self.cdir.to_string()),
-            ("cdir2", self.cdir2.to_string()),
-            ("cdir3", self.cdir3.to_string()),
-            ("cspeed", self.cspeed.to_string()),
-            ("cspeed2", self.cspeed2.to_string()),
-            ("cspeed3", self.cspeed3.to_string()),
-            ("dewpoint", self.dewpoint.to_string()),
-            ("ice", self.ice.to_string()),
-            ("lat", format!("{:.3}", self.lat)),
-            ("leveltrend", self.leveltrend.to_string()),
-            ("lon", format!("{:.3}", self.lon)),
-            ("preciptype", self.preciptype.to_string()),
-            ("pressure", self.pressure.to_string()),
-            ("pressuretend", self.pressuretend.to_string()),
-            ("salinity", self.salinity.to_string()),
-            ("seastate", self.seastate.to_string()),
-            ("swellheight", self.swellheight.to_string()),
-            ("swellperiod", self.swellperiod.to_string()),
-            ("visgreater", self.visgreater.to_string()),
-            ("visibility", self.visibility.to_string()),
-            ("waterlevel", self.waterlevel.to_string()),
-            ("watertemp", self.watertemp.to_string()),
-            ("waveheight", self.waveheight.to_string()),
-            ("waveperiod", self.waveperiod.to_string()),
-            ("wdir", self.wdir.to_string()),
-            ("wgust", self.wgust.to_string()),
-            ("wgustdir", self.wgustdir.to_string()),
-            ("wspeed", self.wspeed.to_string()),
-        ];
-        let result_vector: Vec<(String, String)> = weather
-            .into_iter()
-            .map(|(first, second)| (first.to_string(), second.to_string()))
-            .collect();
-        result_vector
+        columns_as_query_arguments(&self.columns())
+    }
+}
+
+/// The "not available" and out of range codes for each IMO289 field, as scaled by AIS
+/// Catcher (and matching the defaults used when a field is absent).
+pub const TYPE8_DAC200_FID31_SENTINELS: &[FieldSentinel] = &[
+    FieldSentinel::new("airtemp", -102.4, &[]),
+    FieldSentinel::new("cdepth2", 31.0, &[]),
+    FieldSentinel::new("cdepth3", 31.0, &[]),
+    FieldSentinel::new("cdir", 360.0, &[]),
+    FieldSentinel::new("cdir2", 360.0, &[]),
+    FieldSentinel::new("cdir3", 360.0, &[]),
+    FieldSentinel::new("cspeed", 25.5, &[(25.1, ">= 25.1 knots")]),
+    FieldSentinel::new("cspeed2", 25.5, &[(25.1, ">= 25.1 knots")]),
+    FieldSentinel::new("cspeed3", 25.5, &[(25.1, ">= 25.1 knots")]),
+    FieldSentinel::new("dewpoint", 50.1, &[]),
+    FieldSentinel::new("ice", 3.0, &[]),
+    FieldSentinel::new("lat", 91.0, &[]),
+    FieldSentinel::new("leveltrend", 3.0, &[]),
+    FieldSentinel::new("lon", 181.0, &[]),
+    FieldSentinel::new("preciptype", 7.0, &[]),
+    FieldSentinel::new(
+        "pressure",
+        511.0,
+        &[(0.0, "<= 799 hPa"), (402.0, ">= 1201 hPa")],
+    ),
+    FieldSentinel::new("pressuretend", 3.0, &[]),
+    FieldSentinel::new("salinity", 51.1, &[]),
+    FieldSentinel::new("seastate", 13.0, &[]),
+    FieldSentinel::new("swellheight", 25.5, &[(25.1, ">= 25.1 m")]),
+    FieldSentinel::new("swellperiod", 63.0, &[]),
+    FieldSentinel::new("visibility", 12.7, &[]),
+    FieldSentinel::new("waterlevel", 30.01, &[]),
+    FieldSentinel::new("watertemp", 50.1, &[]),
+    FieldSentinel::new("waveheight", 25.5, &[(25.1, ">= 25.1 m")]),
+    FieldSentinel::new("waveperiod", 63.0, &[]),
+    FieldSentinel::new("wdir", 360.0, &[]),
+    FieldSentinel::new("wgust", 127.0, &[(126.0, ">= 126 knots")]),
+    FieldSentinel::new("wgustdir", 360.0, &[]),
+    FieldSentinel::new("wspeed", 127.0, &[(126.0, ">= 126 knots")]),
+];
+
+/// The ERDDAP data type of each IMO289 field, as used in resources/datasets.xml.
+pub const TYPE8_DAC200_FID31_FIELDS: &[FieldSpec] = &[
+    FieldSpec::new("airtemp", FieldType::Float)
+        .describe("Air Temperature", "Temperature")
+        .units("degrees_C")
+        .standard_name("air_temperature"),
+    FieldSpec::new("cdepth2", FieldType::Byte)
+        .describe("Current Depth (Level 2)", "Currents")
+        .units("m"),
+    FieldSpec::new("cdepth3", FieldType::Byte)
+        .describe("Current Depth (Level 3)", "Currents")
+        .units("m"),
+    FieldSpec::new("cdir", FieldType::UShort)
+        .describe("Surface Current Direction", "Currents")
+        .units("degrees")
+        .standard_name("direction_of_sea_water_velocity"),
+    FieldSpec::new("cdir2", FieldType::UShort)
+        .describe("Current Direction (Level 2)", "Currents")
+        .units("degrees")
+        .standard_name("direction_of_sea_water_velocity"),
+    FieldSpec::new("cdir3", FieldType::UShort)
+        .describe("Current Direction (Level 3)", "Currents")
+        .units("degrees")
+        .standard_name("direction_of_sea_water_velocity"),
+    FieldSpec::new("cspeed", FieldType::Float)
+        .describe("Surface Current Speed", "Currents")
+        .units("knots")
+        .standard_name("sea_water_speed"),
+    FieldSpec::new("cspeed2", FieldType::Float)
+        .describe("Current Speed (Level 2)", "Currents")
+        .units("knots")
+        .standard_name("sea_water_speed"),
+    FieldSpec::new("cspeed3", FieldType::Float)
+        .describe("Current Speed (Level 3)", "Currents")
+        .units("knots")
+        .standard_name("sea_water_speed"),
+    FieldSpec::new("dewpoint", FieldType::Float)
+        .describe("Dew Point Temperature", "Temperature")
+        .units("degrees_C")
+        .standard_name("dew_point_temperature"),
+    FieldSpec::new("ice", FieldType::Byte).describe("Ice Presence", "Unknown"),
+    FieldSpec::new("lat", FieldType::Double)
+        .describe("Latitude", "Location")
+        .units("degrees_north")
+        .standard_name("latitude"),
+    FieldSpec::new("leveltrend", FieldType::Byte).describe("Water Level Trend", "Unknown"),
+    FieldSpec::new("lon", FieldType::Double)
+        .describe("Longitude", "Location")
+        .units("degrees_east")
+        .standard_name("longitude"),
+    FieldSpec::new("preciptype", FieldType::Byte).describe("Precipitation Type", "Meteorology"),
+    FieldSpec::new("pressure", FieldType::UInt)
+        .describe("Air Pressure", "Meteorology")
+        .units("hPa")
+        .standard_name("air_pressure"),
+    FieldSpec::new("pressuretend", FieldType::Byte)
+        .describe("Air Pressure Tendency", "Meteorology")
+        .standard_name("tendency_of_air_pressure"),
+    FieldSpec::new("salinity", FieldType::Float)
+        .describe("Salinity", "Salinity")
+        .units("PSU")
+        .standard_name("sea_water_salinity"),
+    FieldSpec::new("seastate", FieldType::Byte)
+        .describe("Sea State", "Surface Waves")
+        .units("Beaufort"),
+    FieldSpec::new("swellheight", FieldType::Float)
+        .describe("Sea Surface Swell Wave Significant Height", "Surface Waves")
+        .units("metres")
+        .standard_name("sea_surface_swell_wave_significant_height"),
+    FieldSpec::new("swellperiod", FieldType::Byte)
+        .describe("Sea Surface Swell Wave Period", "Surface Waves")
+        .units("seconds")
+        .standard_name("sea_surface_swell_wave_period"),
+    FieldSpec::new("visgreater", FieldType::Byte)
+        .describe("Visibility Greater Than Reported", "Meteorology"),
+    FieldSpec::new("visibility", FieldType::Float)
+        .describe("Visibility", "Meteorology")
+        .units("nm")
+        .standard_name("visibility_in_air"),
+    FieldSpec::new("waterlevel", FieldType::Float)
+        .describe("Water Level", "Unknown")
+        .units("metres"),
+    FieldSpec::new("watertemp", FieldType::Float)
+        .describe("Water Temperature", "Temperature")
+        .units("degrees_C")
+        .standard_name("sea_water_temperature"),
+    FieldSpec::new("waveheight", FieldType::Float)
+        .describe("Sea Surface Wave Significant Height", "Surface Waves")
+        .units("metres")
+        .standard_name("sea_surface_wave_significant_height"),
+    FieldSpec::new("waveperiod", FieldType::UShort)
+        .describe("Sea Surface Wave Period", "Surface Waves")
+        .units("seconds")
+        .standard_name("sea_surface_wave_mean_period"),
+    FieldSpec::new("wdir", FieldType::UShort)
+        .describe("Wind Direction", "Wind")
+        .units("degrees")
+        .standard_name("wind_from_direction"),
+    FieldSpec::new("wgust", FieldType::Byte)
+        .describe("Wind Speed Of Gust", "Wind")
+        .units("knots")
+        .standard_name("wind_speed_of_gust"),
+    FieldSpec::new("wgustdir", FieldType::UShort)
+        .describe("Wind Direction Of Gust", "Wind")
+        .units("degrees")
+        .standard_name("wind_gust_from_direction"),
+    FieldSpec::new("wspeed", FieldType::Byte)
+        .describe("Wind Speed (10 minute average)", "Wind")
+        .units("knots")
+        .standard_name("wind_speed"),
+];
+
+/// Structure to hold the data from a Class A position report, types 1, 2 and 3.
+/// AIS Catcher provides scaled data.
+#[derive(Debug, Default)]
+pub struct AisPositionReportClassA {
+    /// Longitude in degrees, east is positive, west is negative. 181 = N/A
+    pub lon: f64,
+    /// Latitude in degrees, north is positive, south is negative. 91 = N/A
+    pub lat: f64,
+    /// Speed over ground in knots, 0.1 knot steps. 102.2 = speed >= 102.2 knots, 102.3 = N/A
+    pub speed: f64,
+    /// Course over ground in degrees true, 0.1 degree steps. 360 = N/A
+    pub course: f64,
+    /// True heading in degrees, 0-359. 511 = N/A
+    pub heading: u64,
+    /// Navigation status, 0 = under way using engine ... 14 = AIS-SART, 15 = not defined
+    pub status: u64,
+    /// Rate of turn in degrees per minute, as reported by AIS-catcher. -128 = N/A
+    pub turn: f64,
+}
+
+/// Extracts fields from the AisMessage structure, and produces an AisPositionReportClassA
+/// structure
+impl TryFrom<&AisMessage> for AisPositionReportClassA {
+    type Error = DecodeError;
+
+    fn try_from(f: &AisMessage) -> Result<Self, Self::Error> {
+        // Defaults are the N/A values from
+        // https://gpsd.gitlab.io/gpsd/AIVDM.html#_types_1_2_and_3_position_report_class_a
+        Ok(AisPositionReportClassA {
+            course: load_f64(&f.msg, "course", 360_f64)?,
+            heading: load_u64(&f.msg, "heading", 511)?,
+            lat: load_f64(&f.msg, "lat", 91_f64)?,
+            lon: load_f64(&f.msg, "lon", 181_f64)?,
+            speed: load_f64(&f.msg, "speed", 102.3)?,
+            status: load_u64(&f.msg, "status", 15)?,
+            turn: load_f64(&f.msg, "turn", -128_f64)?,
+        })
+    }
+}
+
+/// Converts an AisPositionReportClassA into columns, and from there into a set of key/value
+/// pairs that line up with what the ERDDAP system is configured to store.
+impl AisPositionReportClassA {
+    pub fn columns(&self) -> Vec<Column> {
+        vec![
+            Column::float("course", self.course),
+            Column::unsigned("heading", self.heading),
+            Column::fixed("lat", self.lat, 6),
+            Column::fixed("lon", self.lon, 6),
+            Column::float("speed", self.speed),
+            Column::unsigned("status", self.status),
+            Column::float("turn", self.turn),
+        ]
+    }
+
+    pub fn as_query_arguments(&self) -> Vec<(String, String)> {
+        columns_as_query_arguments(&self.columns())
+    }
+}
+
+/// The "not available" and out of range codes for each Class A position report field.
+pub const CLASS_A_POSITION_SENTINELS: &[FieldSentinel] = &[
+    FieldSentinel::new("course", 360.0, &[]),
+    FieldSentinel::new("heading", 511.0, &[]),
+    FieldSentinel::new("lat", 91.0, &[]),
+    FieldSentinel::new("lon", 181.0, &[]),
+    FieldSentinel::new("speed", 102.3, &[(102.2, ">= 102.2 knots")]),
+    FieldSentinel::new("status", 15.0, &[]),
+    FieldSentinel::new("turn", -128.0, &[]),
+];
+
+/// The ERDDAP data type of each Class A position report field.
+pub const CLASS_A_POSITION_FIELDS: &[FieldSpec] = &[
+    FieldSpec::new("course", FieldType::Float)
+        .describe("Course Over Ground", "Other")
+        .units("degrees")
+        .standard_name("platform_course"),
+    FieldSpec::new("heading", FieldType::UShort)
+        .describe("True Heading", "Other")
+        .units("degrees")
+        .standard_name("platform_orientation"),
+    FieldSpec::new("lat", FieldType::Double)
+        .describe("Latitude", "Location")
+        .units("degrees_north")
+        .standard_name("latitude"),
+    FieldSpec::new("lon", FieldType::Double)
+        .describe("Longitude", "Location")
+        .units("degrees_east")
+        .standard_name("longitude"),
+    FieldSpec::new("speed", FieldType::Float)
+        .describe("Speed Over Ground", "Other")
+        .units("knots")
+        .standard_name("platform_speed_wrt_ground"),
+    FieldSpec::new("status", FieldType::Byte).describe("Navigation Status", "Other"),
+    FieldSpec::new("turn", FieldType::Float)
+        .describe("Rate Of Turn", "Other")
+        .units("degrees/minute"),
+];
+
+/// Structure to hold the data from a Class B position report, types 18 and 19. Class B
+/// transponders don't report navigation status or rate of turn.
+/// AIS Catcher provides scaled data.
+#[derive(Debug, Default)]
+pub struct AisPositionReportClassB {
+    /// Longitude in degrees, east is positive, west is negative. 181 = N/A
+    pub lon: f64,
+    /// Latitude in degrees, north is positive, south is negative. 91 = N/A
+    pub lat: f64,
+    /// Speed over ground in knots, 0.1 knot steps. 102.2 = speed >= 102.2 knots, 102.3 = N/A
+    pub speed: f64,
+    /// Course over ground in degrees true, 0.1 degree steps. 360 = N/A
+    pub course: f64,
+    /// True heading in degrees, 0-359. 511 = N/A
+    pub heading: u64,
+}
+
+/// Extracts fields from the AisMessage structure, and produces an AisPositionReportClassB
+/// structure
+impl TryFrom<&AisMessage> for AisPositionReportClassB {
+    type Error = DecodeError;
+
+    fn try_from(f: &AisMessage) -> Result<Self, Self::Error> {
+        // Defaults are the N/A values from
+        // https://gpsd.gitlab.io/gpsd/AIVDM.html#_type_18_standard_class_b_cs_position_report
+        Ok(AisPositionReportClassB {
+            course: load_f64(&f.msg, "course", 360_f64)?,
+            heading: load_u64(&f.msg, "heading", 511)?,
+            lat: load_f64(&f.msg, "lat", 91_f64)?,
+            lon: load_f64(&f.msg, "lon", 181_f64)?,
+            speed: load_f64(&f.msg, "speed", 102.3)?,
+        })
+    }
+}
+
+/// Converts an AisPositionReportClassB into columns, and from there into a set of key/value
+/// pairs that line up with what the ERDDAP system is configured to store.
+impl AisPositionReportClassB {
+    pub fn columns(&self) -> Vec<Column> {
+        vec![
+            Column::float("course", self.course),
+            Column::unsigned("heading", self.heading),
+            Column::fixed("lat", self.lat, 6),
+            Column::fixed("lon", self.lon, 6),
+            Column::float("speed", self.speed),
+        ]
+    }
+
+    pub fn as_query_arguments(&self) -> Vec<(String, String)> {
+        columns_as_query_arguments(&self.columns())
+    }
+}
+
+/// The "not available" and out of range codes for each Class B position report field.
+pub const CLASS_B_POSITION_SENTINELS: &[FieldSentinel] = &[
+    FieldSentinel::new("course", 360.0, &[]),
+    FieldSentinel::new("heading", 511.0, &[]),
+    FieldSentinel::new("lat", 91.0, &[]),
+    FieldSentinel::new("lon", 181.0, &[]),
+    FieldSentinel::new("speed", 102.3, &[(102.2, ">= 102.2 knots")]),
+];
+
+/// The ERDDAP data type of each Class B position report field.
+pub const CLASS_B_POSITION_FIELDS: &[FieldSpec] = &[
+    FieldSpec::new("course", FieldType::Float)
+        .describe("Course Over Ground", "Other")
+        .units("degrees")
+        .standard_name("platform_course"),
+    FieldSpec::new("heading", FieldType::UShort)
+        .describe("True Heading", "Other")
+        .units("degrees")
+        .standard_name("platform_orientation"),
+    FieldSpec::new("lat", FieldType::Double)
+        .describe("Latitude", "Location")
+        .units("degrees_north")
+        .standard_name("latitude"),
+    FieldSpec::new("lon", FieldType::Double)
+        .describe("Longitude", "Location")
+        .units("degrees_east")
+        .standard_name("longitude"),
+    FieldSpec::new("speed", FieldType::Float)
+        .describe("Speed Over Ground", "Other")
+        .units("knots")
+        .standard_name("platform_speed_wrt_ground"),
+];
+
+/// The ERDDAP data types a field can be stored as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Byte,
+    UByte,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double,
+    String,
+}
+
+impl FieldType {
+    /// Parse the data type name ERDDAP uses in datasets.xml and its info pages.
+    pub fn from_erddap(name: &str) -> Option<FieldType> {
+        match name {
+            "byte" => Some(FieldType::Byte),
+            "ubyte" => Some(FieldType::UByte),
+            "short" => Some(FieldType::Short),
+            "ushort" => Some(FieldType::UShort),
+            "int" => Some(FieldType::Int),
+            "uint" => Some(FieldType::UInt),
+            "long" => Some(FieldType::Long),
+            "ulong" => Some(FieldType::ULong),
+            "float" => Some(FieldType::Float),
+            "double" => Some(FieldType::Double),
+            "String" | "char" => Some(FieldType::String),
+            _ => None,
+        }
+    }
+
+    /// The smallest and largest values an integer type can hold.
+    fn integer_range(&self) -> Option<(f64, f64)> {
+        match self {
+            FieldType::Byte => Some((i8::MIN as f64, i8::MAX as f64)),
+            FieldType::UByte => Some((0.0, u8::MAX as f64)),
+            FieldType::Short => Some((i16::MIN as f64, i16::MAX as f64)),
+            FieldType::UShort => Some((0.0, u16::MAX as f64)),
+            FieldType::Int => Some((i32::MIN as f64, i32::MAX as f64)),
+            FieldType::UInt => Some((0.0, u32::MAX as f64)),
+            FieldType::Long => Some((i64::MIN as f64, i64::MAX as f64)),
+            FieldType::ULong => Some((0.0, u64::MAX as f64)),
+            _ => None,
+        }
+    }
+
+    /// Whether a variable of this type can store every value of the `other` type.
+    pub fn can_hold(&self, other: FieldType) -> bool {
+        match (self, other) {
+            (FieldType::String, _) => true,
+            (_, FieldType::String) => false,
+            (FieldType::Double, _) => true,
+            (FieldType::Float, other) => other != FieldType::Double,
+            _ => match (self.integer_range(), other.integer_range()) {
+                (Some((min, max)), Some((other_min, other_max))) => {
+                    min <= other_min && max >= other_max
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FieldType::Byte => "byte",
+            FieldType::UByte => "ubyte",
+            FieldType::Short => "short",
+            FieldType::UShort => "ushort",
+            FieldType::Int => "int",
+            FieldType::UInt => "uint",
+            FieldType::Long => "long",
+            FieldType::ULong => "ulong",
+            FieldType::Float => "float",
+            FieldType::Double => "double",
+            FieldType::String => "String",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Describes a column that a decoder produces, so that the configuration can be checked
+/// against an ERDDAP dataset, and a matching dataset definition generated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldSpec {
+    /// Field name, as produced by the decoder
+    pub name: &'static str,
+    pub data_type: FieldType,
+    /// Human-readable name, for ERDDAP's long_name attribute
+    pub long_name: &'static str,
+    /// One of ERDDAP's fixed list of IOOS categories
+    pub ioos_category: &'static str,
+    /// Units, in the form ERDDAP's units attribute takes
+    pub units: Option<&'static str>,
+    /// CF standard name, where there is one
+    pub standard_name: Option<&'static str>,
+}
+
+impl FieldSpec {
+    pub const fn new(name: &'static str, data_type: FieldType) -> Self {
+        FieldSpec {
+            name,
+            data_type,
+            long_name: name,
+            ioos_category: "Unknown",
+            units: None,
+            standard_name: None,
+        }
+    }
+
+    pub const fn describe(mut self, long_name: &'static str, ioos_category: &'static str) -> Self {
+        self.long_name = long_name;
+        self.ioos_category = ioos_category;
+        self
+    }
+
+    pub const fn units(mut self, units: &'static str) -> Self {
+        self.units = Some(units);
+        self
+    }
+
+    pub const fn standard_name(mut self, standard_name: &'static str) -> Self {
+        self.standard_name = Some(standard_name);
+        self
+    }
+}
+
+/// The codes a field uses in place of a measurement. AIS packs "no data" and "beyond the
+/// sensor's range" into special values of the field itself, which look like (very wrong)
+/// measurements if they're passed on as-is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldSentinel {
+    /// Field name, as produced by the decoder
+    pub field: &'static str,
+    /// The value that means no data is available
+    pub not_available: f64,
+    /// Values that mean the measurement was off the scale, and what they mean
+    pub out_of_range: &'static [(f64, &'static str)],
+}
+
+impl FieldSentinel {
+    pub const fn new(
+        field: &'static str,
+        not_available: f64,
+        out_of_range: &'static [(f64, &'static str)],
+    ) -> Self {
+        FieldSentinel {
+            field,
+            not_available,
+            out_of_range,
+        }
+    }
+
+    /// Whether the value is one of this field's codes rather than a measurement.
+    pub fn matches(&self, value: f64) -> bool {
+        let same = |code: f64| (code - value).abs() < 1e-9;
+        same(self.not_available) || self.out_of_range.iter().any(|(code, _)| same(*code))
+    }
+}
+
+/// What to send to ERDDAP in place of a field's not available or out of range codes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotAvailableMode {
+    /// Send the codes as they are, as AIS-catcher reports them
+    #[default]
+    Sentinel,
+    /// Send ERDDAP's missing value (NaN) instead
+    Missing,
+    /// Leave the field out of the row altogether
+    Omit,
+}
+
+/// Replace or remove any column whose value is one of its field's sentinel codes, as
+/// directed by the mode.
+pub fn apply_not_available(
+    columns: Vec<Column>,
+    sentinels: &[FieldSentinel],
+    mode: NotAvailableMode,
+) -> Vec<Column> {
+    if mode == NotAvailableMode::Sentinel {
+        return columns;
+    }
+    columns
+        .into_iter()
+        .filter_map(|column| {
+            let is_sentinel = match (
+                &column.value,
+                sentinels.iter().find(|s| s.field == column.name),
+            ) {
+                (ColumnValue::Float(val, _), Some(sentinel)) => sentinel.matches(*val),
+                (ColumnValue::Integer(val), Some(sentinel)) => sentinel.matches(*val as f64),
+                (ColumnValue::Unsigned(val), Some(sentinel)) => sentinel.matches(*val as f64),
+                _ => false,
+            };
+            match (is_sentinel, mode) {
+                (false, _) => Some(column),
+                (true, NotAvailableMode::Omit) => None,
+                (true, _) => Some(Column::new(&column.name, ColumnValue::Missing)),
+            }
+        })
+        .collect()
+}
+
+/// The value of a decoded column, keeping enough of its type to format it for ERDDAP.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnValue {
+    /// A floating point value, optionally with a fixed number of decimal places
+    Float(f64, Option<usize>),
+    /// A signed integer value
+    Integer(i64),
+    /// An unsigned integer value
+    Unsigned(u64),
+    /// A string value
+    Text(String),
+    /// No usable value; sent to ERDDAP as its missing value, NaN
+    Missing,
+}
+
+impl fmt::Display for ColumnValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnValue::Float(val, Some(places)) => write!(f, "{:.*}", places, val),
+            ColumnValue::Float(val, None) => write!(f, "{}", val),
+            ColumnValue::Integer(val) => write!(f, "{}", val),
+            ColumnValue::Unsigned(val) => write!(f, "{}", val),
+            ColumnValue::Text(val) => write!(f, "{}", val),
+            ColumnValue::Missing => write!(f, "NaN"),
+        }
+    }
+}
+
+/// A named value decoded from an AIS message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub value: ColumnValue,
+}
+
+impl Column {
+    pub fn new(name: &str, value: ColumnValue) -> Self {
+        Column {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    pub fn float(name: &str, value: f64) -> Self {
+        Column::new(name, ColumnValue::Float(value, None))
+    }
+
+    /// A floating point column that is always formatted with `places` decimal places
+    pub fn fixed(name: &str, value: f64, places: usize) -> Self {
+        Column::new(name, ColumnValue::Float(value, Some(places)))
+    }
+
+    pub fn unsigned(name: &str, value: u64) -> Self {
+        Column::new(name, ColumnValue::Unsigned(value))
+    }
+}
+
+/// Converts columns into the key/value pairs used in an ERDDAP query.
+pub fn columns_as_query_arguments(columns: &[Column]) -> Vec<(String, String)> {
+    columns
+        .iter()
+        .map(|column| (column.name.clone(), column.value.to_string()))
+        .collect()
+}
+
+/// Turns an AIS message of a particular type into named, typed columns. Decoders are
+/// looked up by message identifier in a DecoderRegistry.
+pub trait MessageDecoder: Send + Sync {
+    /// Short, human-friendly name for the kind of message decoded, for logging
+    fn name(&self) -> &str;
+    /// Decode the message body into columns
+    fn decode(&self, msg: &AisMessage) -> Result<Vec<Column>, DecodeError>;
+    /// The not available and out of range codes used by the decoded fields
+    fn sentinels(&self) -> &'static [FieldSentinel] {
+        &[]
+    }
+    /// Every field the decoder can produce, with its type. Decoders that leave this empty
+    /// can't be checked against an ERDDAP dataset.
+    fn fields(&self) -> &'static [FieldSpec] {
+        &[]
+    }
+}
+
+/// Decoder for IMO289 weather messages, type 8 DAC 200 FID 31
+pub struct Type8Dac200Fid31Decoder;
+
+impl MessageDecoder for Type8Dac200Fid31Decoder {
+    fn name(&self) -> &str {
+        "imo289_met_hydro"
+    }
+
+    fn decode(&self, msg: &AisMessage) -> Result<Vec<Column>, DecodeError> {
+        Ok(AisType8Dac200Fid31::try_from(msg)?.columns())
+    }
+
+    fn sentinels(&self) -> &'static [FieldSentinel] {
+        TYPE8_DAC200_FID31_SENTINELS
+    }
+
+    fn fields(&self) -> &'static [FieldSpec] {
+        TYPE8_DAC200_FID31_FIELDS
+    }
+}
+
+/// Decoder for Class A position reports, types 1, 2 and 3
+pub struct ClassAPositionDecoder;
+
+impl MessageDecoder for ClassAPositionDecoder {
+    fn name(&self) -> &str {
+        "class_a_position"
+    }
+
+    fn decode(&self, msg: &AisMessage) -> Result<Vec<Column>, DecodeError> {
+        Ok(AisPositionReportClassA::try_from(msg)?.columns())
+    }
+
+    fn sentinels(&self) -> &'static [FieldSentinel] {
+        CLASS_A_POSITION_SENTINELS
+    }
+
+    fn fields(&self) -> &'static [FieldSpec] {
+        CLASS_A_POSITION_FIELDS
+    }
+}
+
+/// Decoder for Class B position reports, types 18 and 19
+pub struct ClassBPositionDecoder;
+
+impl MessageDecoder for ClassBPositionDecoder {
+    fn name(&self) -> &str {
+        "class_b_position"
+    }
+
+    fn decode(&self, msg: &AisMessage) -> Result<Vec<Column>, DecodeError> {
+        Ok(AisPositionReportClassB::try_from(msg)?.columns())
+    }
+
+    fn sentinels(&self) -> &'static [FieldSentinel] {
+        CLASS_B_POSITION_SENTINELS
+    }
+
+    fn fields(&self) -> &'static [FieldSpec] {
+        CLASS_B_POSITION_FIELDS
+    }
+}
+
+/// Maps message identifiers to the decoders that handle them. Library users can register
+/// their own decoders for message types that the feeder doesn't know about, or replace
+/// the built-in ones.
+#[derive(Clone, Default)]
+pub struct DecoderRegistry {
+    decoders: HashMap<AisMessageIdentifier, Arc<dyn MessageDecoder>>,
+}
+
+impl DecoderRegistry {
+    /// A registry with no decoders in it
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with decoders for every message type the feeder supports out of the box
+    pub fn with_builtin_decoders() -> Self {
+        let mut registry = Self::new();
+        registry.register(
+            AisMessageIdentifier {
+                r#type: 8,
+                dac: Some(200),
+                fid: Some(31),
+            },
+            Arc::new(Type8Dac200Fid31Decoder),
+        );
+        let class_a: Arc<dyn MessageDecoder> = Arc::new(ClassAPositionDecoder);
+        for r#type in [1, 2, 3] {
+            registry.register(
+                AisMessageIdentifier {
+                    r#type,
+                    dac: None,
+                    fid: None,
+                },
+                class_a.clone(),
+            );
+        }
+        let class_b: Arc<dyn MessageDecoder> = Arc::new(ClassBPositionDecoder);
+        for r#type in [18, 19] {
+            registry.register(
+                AisMessageIdentifier {
+                    r#type,
+                    dac: None,
+                    fid: None,
+                },
+                class_b.clone(),
+            );
+        }
+        registry
+    }
+
+    /// Add a decoder for a message identifier, replacing any existing one.
+    pub fn register(&mut self, ami: AisMessageIdentifier, decoder: Arc<dyn MessageDecoder>) {
+        self.decoders.insert(ami, decoder);
+    }
+
+    /// The decoder for a message identifier, if one is registered.
+    pub fn get(&self, ami: &AisMessageIdentifier) -> Option<&Arc<dyn MessageDecoder>> {
+        self.decoders.get(ami)
     }
 }
 
 /// Application configuration from file
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct AppConfig {
     /// URL of the ERDDAP service, including protocol and path, not including .insert
     pub erddap_url: String,
     /// Username_Password author key for the ERDDAP service
     pub erddap_key: String,
+    /// File to read the author key from instead, such as a Docker secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub erddap_key_file: Option<PathBuf>,
     /// Whether to accept invalid certificates, like unknown CA, expired, not for the
     /// hostname etcetera
     pub accept_invalid_certificates: bool,
@@ -350,24 +1241,131 @@ pub struct AppConfig {
     pub publish_fields: Vec<String>,
     /// A remapping of field names to support requirements of the remote system
     pub rename_fields: Vec<(String, String)>,
+    /// What to send in place of not available and out of range codes
+    #[serde(default)]
+    pub not_available: NotAvailableMode,
     /// Defines a configuration of acceptable message types, and MMSIs to ignore
     pub message_config: Vec<AcceptedMessage>,
     /// Map MMSIs (Mobile Marine Service Identifier) to string names to provide a
     /// human-friendly station name in the data posted to ERDDAP.
     pub mmsi_lookup: Vec<MMSILookup>,
+    /// CSV or JSON file of further mmsi_lookup entries, kept outside the configuration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmsi_registry: Option<PathBuf>,
+    /// Further ERDDAP services that every accepted row is also sent to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<ErddapSink>,
+    /// Directory to also write the default sink's rows to, as daily JSONL files that ERDDAP
+    /// can read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jsonl_dir: Option<PathBuf>,
+    /// Where to also write the default sink's rows as plain CSV files, and how often to
+    /// start new ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csv_output: Option<CsvOutputConfig>,
+    /// InfluxDB bucket to also write each accepted message to, for dashboards
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub influxdb: Option<InfluxConfig>,
+    /// SQLite database to keep a local history of decoded messages in, served on
+    /// /observations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sqlite_path: Option<PathBuf>,
+}
+
+/// Shown in place of author keys, so that configuration can be logged safely
+pub const REDACTED: &str = "REDACTED";
+
+// The author keys are left out of the Debug output of everything that holds them.
+impl fmt::Debug for AppConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AppConfig")
+            .field("erddap_url", &self.erddap_url)
+            .field("erddap_key", &REDACTED)
+            .field("erddap_key_file", &self.erddap_key_file)
+            .field(
+                "accept_invalid_certificates",
+                &self.accept_invalid_certificates,
+            )
+            .field("publish_fields", &self.publish_fields)
+            .field("rename_fields", &self.rename_fields)
+            .field("not_available", &self.not_available)
+            .field("message_config", &self.message_config)
+            .field("mmsi_lookup", &self.mmsi_lookup)
+            .field("mmsi_registry", &self.mmsi_registry)
+            .field("sinks", &self.sinks)
+            .field("jsonl_dir", &self.jsonl_dir)
+            .field("csv_output", &self.csv_output)
+            .field("influxdb", &self.influxdb)
+            .field("sqlite_path", &self.sqlite_path)
+            .finish()
+    }
 }
 
-/// A TOML table entry for a MMSI and the station name to use for that MMSI
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// A TOML table entry (or MMSI registry row) for a MMSI and the station name to use for
+/// that MMSI
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct MMSILookup {
     /// The Marine Mobile Service Identifier from the AIS message
     pub mmsi: String,
     /// The name to give the MMSI.
     pub station_name: String,
+    /// Where the station nominally is; AIS weather reports carry their own position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// Who runs the station
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// A TOML table for the CSV files rows are written to
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CsvOutputConfig {
+    /// Directory the files are written under
+    pub dir: PathBuf,
+    /// How much time each file covers: hourly, daily or monthly
+    #[serde(default)]
+    pub rotation: output::CsvRotation,
+}
+
+/// A TOML table for the InfluxDB v2 bucket messages are written to
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct InfluxConfig {
+    /// Base URL of the InfluxDB server, such as http://localhost:8086
+    pub url: String,
+    /// Organization the bucket belongs to
+    pub org: String,
+    pub bucket: String,
+    /// API token with write access to the bucket
+    #[serde(default)]
+    pub token: String,
+    /// File to read the token from instead, such as a Docker secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    /// Whether to accept invalid certificates from the InfluxDB server
+    #[serde(default)]
+    pub accept_invalid_certificates: bool,
+}
+
+impl fmt::Debug for InfluxConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InfluxConfig")
+            .field("url", &self.url)
+            .field("org", &self.org)
+            .field("bucket", &self.bucket)
+            .field("token", &REDACTED)
+            .field("token_file", &self.token_file)
+            .field(
+                "accept_invalid_certificates",
+                &self.accept_invalid_certificates,
+            )
+            .finish()
+    }
 }
 
 /// A TOML table entry for a packet to accept for decoding
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct AcceptedMessage {
     /// The type number from the AIS specification, such as 8 for weather
     pub r#type: u64,
@@ -377,6 +1375,98 @@ pub struct AcceptedMessage {
     pub fid: Option<u64>,
     /// List of MMSIs to ignore, such as test ATONs.
     pub ignore_mmsi: Vec<u64>,
+    /// URL of the ERDDAP dataset to send this message type to, if not the global erddap_url
+    pub erddap_url: Option<String>,
+    /// Author key for erddap_url, if not the global erddap_key
+    pub erddap_key: Option<String>,
+    /// Fields to publish for this message type, if not the global publish_fields
+    pub publish_fields: Option<Vec<String>>,
+    /// Field renames for this message type, if not the global rename_fields
+    pub rename_fields: Option<Vec<(String, String)>>,
+}
+
+impl fmt::Debug for AcceptedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AcceptedMessage")
+            .field("type", &self.r#type)
+            .field("dac", &self.dac)
+            .field("fid", &self.fid)
+            .field("ignore_mmsi", &self.ignore_mmsi)
+            .field("erddap_url", &self.erddap_url)
+            .field("erddap_key", &self.erddap_key.as_ref().map(|_| REDACTED))
+            .field("publish_fields", &self.publish_fields)
+            .field("rename_fields", &self.rename_fields)
+            .finish()
+    }
+}
+
+/// A TOML table entry for a further ERDDAP service, such as a partner's, that every
+/// accepted row is also sent to
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ErddapSink {
+    /// Name for the sink in logs, metrics, /readyz and the name of its retry spool
+    pub name: String,
+    /// URL of the sink's ERDDAP dataset, not including .insert
+    pub erddap_url: String,
+    /// Username_Password author key for the sink's dataset
+    #[serde(default)]
+    pub erddap_key: String,
+    /// File to read the author key from instead, such as a Docker secret
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub erddap_key_file: Option<PathBuf>,
+    /// Whether to accept invalid certificates from the sink's ERDDAP service
+    #[serde(default)]
+    pub accept_invalid_certificates: bool,
+    /// Fields to send to this sink, if not those of each message type
+    #[serde(default)]
+    pub publish_fields: Option<Vec<String>>,
+    /// Field renames for this sink, if not those of each message type
+    #[serde(default)]
+    pub rename_fields: Option<Vec<(String, String)>>,
+}
+
+impl fmt::Debug for ErddapSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ErddapSink")
+            .field("name", &self.name)
+            .field("erddap_url", &self.erddap_url)
+            .field("erddap_key", &REDACTED)
+            .field("erddap_key_file", &self.erddap_key_file)
+            .field(
+                "accept_invalid_certificates",
+                &self.accept_invalid_certificates,
+            )
+            .field("publish_fields", &self.publish_fields)
+            .field("rename_fields", &self.rename_fields)
+            .finish()
+    }
+}
+
+/// An ERDDAP dataset that rows are sent to
+#[derive(Clone, PartialEq)]
+pub struct ErddapDestination {
+    /// The sink the dataset belongs to
+    pub sink: String,
+    /// URL of the ERDDAP dataset, including protocol and path, not including .insert
+    pub url: String,
+    /// Username_Password author key for the dataset
+    pub author_key: String,
+    /// Whether to accept invalid certificates from the dataset's ERDDAP service
+    pub accept_invalid_certificates: bool,
+}
+
+impl fmt::Debug for ErddapDestination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ErddapDestination")
+            .field("sink", &self.sink)
+            .field("url", &self.url)
+            .field("author_key", &REDACTED)
+            .field(
+                "accept_invalid_certificates",
+                &self.accept_invalid_certificates,
+            )
+            .finish()
+    }
 }
 
 /// Used to pass configuration data into the ArgsState struct for passing around in the
@@ -385,6 +1475,22 @@ pub struct AcceptedMessage {
 pub struct PerMessageConfig {
     /// List of MMSIs to ignore, such as test ATONs.
     pub ignore_mmsi: Vec<u64>,
+    /// Where rows from this message type are sent
+    pub destination: ErddapDestination,
+    /// Field names to publish; empty means publish everything
+    pub publish_fields: Vec<String>,
+    /// Map of decoded field name to the name ERDDAP expects
+    pub rename_fields: HashMap<String, String>,
+}
+
+/// A further ERDDAP service that accepted rows are sent to, alongside the default sink.
+#[derive(Debug, Clone)]
+pub struct PerSinkConfig {
+    pub name: String,
+    /// The sink's dataset and field handling for each accepted message type
+    pub message_config_lookup: HashMap<AisMessageIdentifier, PerMessageConfig>,
+    /// Rows that failed to reach this sink, waiting to be retried
+    pub spool: spool::SharedSpool,
 }
 
 impl ::std::default::Default for AppConfig {
@@ -392,6 +1498,7 @@ impl ::std::default::Default for AppConfig {
         Self {
             erddap_url: DEFAULT_URL.to_string(),
             erddap_key: DEFAULT_KEY.to_string(),
+            erddap_key_file: None,
             // Default mode is safe mode - only accept certificates with a good chain and
             // recognised root certificates.
             accept_invalid_certificates: false,
@@ -409,16 +1516,28 @@ impl ::std::default::Default for AppConfig {
                 ("lat".to_string(), "latitude".to_string()),
                 ("lon".to_string(), "longitude".to_string()),
             ],
+            not_available: NotAvailableMode::Sentinel,
             message_config: vec![AcceptedMessage {
                 r#type: 8,
                 dac: Some(200),
                 fid: Some(31),
                 ignore_mmsi: vec![],
+                erddap_url: None,
+                erddap_key: None,
+                publish_fields: None,
+                rename_fields: None,
             }],
             mmsi_lookup: vec![MMSILookup {
                 mmsi: DEFAULT_MMSI.to_string(),
                 station_name: "MMSI Name".to_string(),
+                ..Default::default()
             }],
+            mmsi_registry: None,
+            sinks: vec![],
+            jsonl_dir: None,
+            csv_output: None,
+            influxdb: None,
+            sqlite_path: None,
         }
     }
 }
@@ -437,13 +1556,339 @@ pub struct ErddapResponse {
 /// CLI state data for Axum to pass around; everything from args has to be in here
 #[derive(Clone)]
 pub struct ArgsState {
+    /// The global ERDDAP dataset URL, for message types that don't name their own
     pub url: String,
+    /// The global ERDDAP author key
     pub author_key: String,
     pub accept_invalid_certificates: bool,
-    pub publish_fields: Vec<String>,
-    pub rename_fields: HashMap<String, String>,
     pub dump_all_packets: bool,
     pub dump_accepted_messages: bool,
     pub mmsi_lookup: HashMap<String, String>,
+    /// The whole mmsi_lookup (or registry) entry for each MMSI, with the station's nominal
+    /// position and owner if they're known
+    pub stations: HashMap<String, MMSILookup>,
     pub message_config_lookup: HashMap<AisMessageIdentifier, PerMessageConfig>,
+    /// The sinks every accepted row is also sent to
+    pub sinks: Vec<PerSinkConfig>,
+    /// Rows that failed to reach the default sink, waiting to be retried
+    pub spool: spool::SharedSpool,
+    /// Local files the default sink's rows are also written to
+    pub outputs: Vec<Arc<dyn output::RowOutput>>,
+    /// The InfluxDB bucket accepted messages are also written to, if there is one
+    pub influxdb: Option<InfluxConfig>,
+    /// The local history of decoded messages, if one is kept
+    pub store: Option<Arc<store::Store>>,
+    /// Rows waiting for the batch window to close, with the URL they're destined for
+    pub pending_rows: Arc<Mutex<Vec<(String, QueryRow)>>>,
+    /// How long to collect rows across packets before sending; zero sends per packet
+    pub batch_window: Duration,
+    /// Most rows to send in a single .insert request
+    pub batch_max_rows: usize,
+    /// Decoders for the message types the handler can convert
+    pub decoders: DecoderRegistry,
+    /// What to send in place of not available and out of range codes
+    pub not_available: NotAvailableMode,
+    /// Counters and gauges served on /metrics
+    pub metrics: metrics::Metrics,
+    /// The latest successful and failed exchanges with ERDDAP, for /readyz
+    pub health: Arc<Mutex<health::ErddapHealth>>,
+    /// How recently ERDDAP must have accepted something for /readyz to pass; zero disables
+    /// the check
+    pub ready_window: Duration,
+    /// Where the raw packets are recorded, if they are
+    pub capture: Option<Arc<Mutex<capture::Capture>>>,
+    /// Log the .insert URLs instead of sending them to ERDDAP
+    pub dry_run: bool,
+}
+
+impl ArgsState {
+    /// The destination an ERDDAP dataset URL belongs to. Rows are queued and spooled with
+    /// only their URL, so the sink, key and certificate policy are looked up when they're
+    /// finally sent. A URL that's no longer in the configuration has no destination: the
+    /// key its rows were meant to be sent with has gone, and another sink's mustn't be
+    /// sent in its place. validate_config makes sure a URL only ever has the one key.
+    pub fn destination_for(&self, url: &str) -> Option<ErddapDestination> {
+        self.destinations()
+            .into_iter()
+            .find(|destination| destination.url == url)
+            .cloned()
+    }
+
+    /// Every destination rows can be sent to, across the sinks
+    pub fn destinations(&self) -> Vec<&ErddapDestination> {
+        self.message_config_lookup
+            .values()
+            .chain(
+                self.sinks
+                    .iter()
+                    .flat_map(|sink| sink.message_config_lookup.values()),
+            )
+            .map(|pmc| &pmc.destination)
+            .collect()
+    }
+
+    /// The retry spool of each sink, by sink name, starting with the default sink
+    pub fn spools(&self) -> Vec<(&str, &spool::SharedSpool)> {
+        let mut spools = vec![(DEFAULT_SINK, &self.spool)];
+        spools.extend(
+            self.sinks
+                .iter()
+                .map(|sink| (sink.name.as_str(), &sink.spool)),
+        );
+        spools
+    }
+
+    /// The retry spool for a sink; sinks no longer in the configuration use the default's
+    pub fn spool_for(&self, sink: &str) -> &spool::SharedSpool {
+        self.sinks
+            .iter()
+            .find(|s| s.name == sink)
+            .map(|s| &s.spool)
+            .unwrap_or(&self.spool)
+    }
+}
+
+/// The ArgsState the feeder is running with. Reloading the configuration swaps in a new
+/// one; requests that are already being handled finish with the state they started with.
+#[derive(Clone)]
+pub struct SharedArgsState(Arc<RwLock<Arc<ArgsState>>>);
+
+impl SharedArgsState {
+    pub fn new(args: ArgsState) -> SharedArgsState {
+        SharedArgsState(Arc::new(RwLock::new(Arc::new(args))))
+    }
+
+    /// The state to use for the work about to be done
+    pub fn current(&self) -> Arc<ArgsState> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, args: ArgsState) {
+        *self.0.write().unwrap() = Arc::new(args);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(json: &str) -> AisMessage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn query_for(registry: &DecoderRegistry, msg: &AisMessage) -> Option<Vec<(String, String)>> {
+        let ami = AisMessageIdentifier::try_from(msg).unwrap();
+        registry
+            .get(&ami)
+            .map(|decoder| columns_as_query_arguments(&decoder.decode(msg).unwrap()))
+    }
+
+    #[test]
+    fn test_builtin_decoders_position_reports() {
+        let registry = DecoderRegistry::with_builtin_decoders();
+        let msg = message(
+            r#"{"type": 1, "mmsi": 250001234, "lat": 53.35, "lon": -6.2, "speed": 10.5,
+                "course": 90.1, "heading": 88, "status": 0, "turn": 0}"#,
+        );
+        let query = query_for(&registry, &msg).unwrap();
+        assert!(query.contains(&("lat".to_string(), "53.350000".to_string())));
+        assert!(query.contains(&("speed".to_string(), "10.5".to_string())));
+        assert!(query.contains(&("status".to_string(), "0".to_string())));
+
+        // Class B has no status, and missing fields fall back to N/A values
+        let msg = message(r#"{"type": 18, "mmsi": 235001234}"#);
+        let query = query_for(&registry, &msg).unwrap();
+        assert!(query.contains(&("heading".to_string(), "511".to_string())));
+        assert!(!query.iter().any(|(key, _)| key == "status"));
+
+        let msg = message(r#"{"type": 5, "mmsi": 235001234}"#);
+        assert!(query_for(&registry, &msg).is_none());
+    }
+
+    #[test]
+    fn test_apply_not_available() {
+        let msg = message(r#"{"type": 8, "dac": 200, "fid": 31, "wspeed": 126, "wgust": 20}"#);
+        let columns = Type8Dac200Fid31Decoder.decode(&msg).unwrap();
+        let find = |columns: &[Column], name: &str| {
+            columns
+                .iter()
+                .find(|c| c.name == name)
+                .map(|c| c.value.clone())
+        };
+
+        let kept = apply_not_available(
+            columns.clone(),
+            TYPE8_DAC200_FID31_SENTINELS,
+            NotAvailableMode::Sentinel,
+        );
+        assert_eq!(find(&kept, "wspeed"), Some(ColumnValue::Unsigned(126)));
+        assert_eq!(find(&kept, "wdir"), Some(ColumnValue::Unsigned(360)));
+
+        let missing = apply_not_available(
+            columns.clone(),
+            TYPE8_DAC200_FID31_SENTINELS,
+            NotAvailableMode::Missing,
+        );
+        assert_eq!(find(&missing, "wspeed"), Some(ColumnValue::Missing));
+        assert_eq!(find(&missing, "wdir"), Some(ColumnValue::Missing));
+        assert_eq!(find(&missing, "wgust"), Some(ColumnValue::Unsigned(20)));
+        assert_eq!(ColumnValue::Missing.to_string(), "NaN");
+
+        let omitted = apply_not_available(
+            columns,
+            TYPE8_DAC200_FID31_SENTINELS,
+            NotAvailableMode::Omit,
+        );
+        assert_eq!(find(&omitted, "airtemp"), None);
+        assert_eq!(find(&omitted, "wgust"), Some(ColumnValue::Unsigned(20)));
+        // Fields without any codes are always kept
+        assert!(find(&omitted, "visgreater").is_some());
+    }
+
+    #[test]
+    fn test_scaled_not_available_codes() {
+        // As AIS-catcher sends it, with its values scaled
+        let msg = message(
+            r#"{"class":"AIS","device":"AIS-catcher","scaled":true,"channel":"B",
+                "signalpower":-38.3,"ppm":-2.5,"rxtime":"20231001120000","type":8,"repeat":0,
+                "mmsi":992501234,"dac":200,"fid":31,"lon":-6.1,"lat":53.3,"accuracy":false,
+                "day":1,"hour":12,"minute":0,"wspeed":12,"wgust":15,"wdir":270,"wgustdir":280,
+                "airtemp":-102.4,"humidity":101,"dewpoint":50.1,"pressure":511,
+                "pressuretend":3,"visibility":12.7,"waterlevel":30.01,"leveltrend":3,
+                "cspeed":25.5,"cdir":360,"cspeed2":25.5,"cdir2":360,"cdepth2":31,"cspeed3":25.5,
+                "cdir3":360,"cdepth3":31,"waveheight":25.5,"waveperiod":63,"wavedir":360,
+                "swellheight":25.5,"swellperiod":63,"swelldir":360,"seastate":13,
+                "watertemp":50.1,"preciptype":7,"salinity":51.1,"ice":3}"#,
+        );
+        let columns = apply_not_available(
+            Type8Dac200Fid31Decoder.decode(&msg).unwrap(),
+            TYPE8_DAC200_FID31_SENTINELS,
+            NotAvailableMode::Missing,
+        );
+        let value = |name: &str| {
+            columns
+                .iter()
+                .find(|c| c.name == name)
+                .map(|c| c.value.clone())
+                .unwrap()
+        };
+        assert_eq!(value("airtemp"), ColumnValue::Missing);
+        assert_eq!(value("salinity"), ColumnValue::Missing);
+        assert_eq!(value("wspeed"), ColumnValue::Unsigned(12));
+
+        let msg = message(
+            r#"{"class":"AIS","scaled":true,"type":8,"dac":200,"fid":31,"mmsi":992501234,
+                "airtemp":-10.2,"salinity":35.1}"#,
+        );
+        let columns = apply_not_available(
+            Type8Dac200Fid31Decoder.decode(&msg).unwrap(),
+            TYPE8_DAC200_FID31_SENTINELS,
+            NotAvailableMode::Missing,
+        );
+        assert!(columns
+            .iter()
+            .any(|c| c.name == "airtemp"
+                && matches!(c.value, ColumnValue::Float(v, _) if v == -10.2)));
+        assert!(columns
+            .iter()
+            .any(|c| c.name == "salinity"
+                && matches!(c.value, ColumnValue::Float(v, _) if v == 35.1)));
+    }
+
+    #[test]
+    fn test_absent_fields_default_to_their_not_available_codes() {
+        let msg = message(r#"{"class":"AIS","type":8,"dac":200,"fid":31,"mmsi":992501234}"#);
+        let decoded = Type8Dac200Fid31Decoder.decode(&msg).unwrap();
+        for sentinel in TYPE8_DAC200_FID31_SENTINELS {
+            let column = decoded.iter().find(|c| c.name == sentinel.field).unwrap();
+            let value = match column.value {
+                ColumnValue::Float(value, _) => value,
+                ColumnValue::Unsigned(value) => value as f64,
+                _ => panic!("{} isn't a number", sentinel.field),
+            };
+            assert_eq!(value, sentinel.not_available, "{}", sentinel.field);
+        }
+        let columns = apply_not_available(
+            decoded,
+            TYPE8_DAC200_FID31_SENTINELS,
+            NotAvailableMode::Missing,
+        );
+        let swellperiod = columns.iter().find(|c| c.name == "swellperiod").unwrap();
+        assert_eq!(swellperiod.value, ColumnValue::Missing);
+    }
+
+    #[test]
+    fn test_decode_errors_name_the_field() {
+        let msg = message(
+            r#"{"type": 8, "mmsi": "992501234", "signalpower": -20.0,
+                "rxtime": "20231001120000"}"#,
+        );
+        assert_eq!(
+            AisStationData::try_from(&msg).unwrap_err(),
+            DecodeError::WrongType {
+                field: "mmsi".to_string(),
+                expected: "an unsigned integer",
+                found: "\"992501234\"".to_string(),
+            }
+        );
+
+        let msg = message(r#"{"type": 8, "mmsi": 992501234, "signalpower": -20.0}"#);
+        assert_eq!(
+            AisStationData::try_from(&msg).unwrap_err(),
+            DecodeError::MissingField("rxtime".to_string())
+        );
+
+        let msg = message(r#"{"type": 8, "mmsi": 1, "signalpower": 1, "rxtime": "yesterday"}"#);
+        assert!(matches!(
+            AisStationData::try_from(&msg).unwrap_err(),
+            DecodeError::InvalidValue { field, .. } if field == "rxtime"
+        ));
+
+        let msg = message(r#"{"dac": 200}"#);
+        assert_eq!(
+            AisMessageIdentifier::try_from(&msg).unwrap_err(),
+            DecodeError::MissingField("type".to_string())
+        );
+
+        let msg = message(r#"{"type": 8, "dac": 200, "fid": 31, "airtemp": "warm"}"#);
+        assert_eq!(
+            Type8Dac200Fid31Decoder
+                .decode(&msg)
+                .unwrap_err()
+                .to_string(),
+            "field 'airtemp' should be a number, found \"warm\""
+        );
+    }
+
+    struct ShipNameDecoder;
+
+    impl MessageDecoder for ShipNameDecoder {
+        fn name(&self) -> &str {
+            "static_data"
+        }
+
+        fn decode(&self, msg: &AisMessage) -> Result<Vec<Column>, DecodeError> {
+            let name = msg.msg["shipname"].as_str().unwrap_or_default();
+            Ok(vec![Column::new(
+                "shipname",
+                ColumnValue::Text(name.to_string()),
+            )])
+        }
+    }
+
+    #[test]
+    fn test_register_custom_decoder() {
+        let mut registry = DecoderRegistry::new();
+        let msg = message(r#"{"type": 5, "mmsi": 235001234, "shipname": "SEA BREEZE"}"#);
+        assert!(query_for(&registry, &msg).is_none());
+
+        registry.register(
+            AisMessageIdentifier::try_from(&msg).unwrap(),
+            Arc::new(ShipNameDecoder),
+        );
+        assert_eq!(
+            query_for(&registry, &msg).unwrap(),
+            vec![("shipname".to_string(), "SEA BREEZE".to_string())]
+        );
+    }
 }
@@ -1,12 +1,43 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::routing::{get, post};
+use axum::{http::StatusCode, response::IntoResponse, Json, Router};
 use clap::{Args, Parser, Subcommand};
-use erddap_feeder::{AisCatcherMessage, AisMessageIdentifier, AisStationData, AisType8Dac200Fid31};
-use erddap_feeder::{AppConfig, ArgsState, ErddapResponse, PerMessageConfig};
-use erddap_feeder::{DEFAULT_KEY, DEFAULT_MMSI, DEFAULT_URL};
+use erddap_feeder::capture::{open_capture_file, Capture, CaptureOptions};
+use erddap_feeder::config::{load_config_path, store_config_path};
+use erddap_feeder::generate::{datasets_xml, GeneratedDataset, GeneratedVariable};
+use erddap_feeder::health::ErddapHealth;
+use erddap_feeder::influx::line_protocol;
+use erddap_feeder::metrics::Metrics;
+use erddap_feeder::output::{CsvOutput, JsonlOutput, RowOutput};
+use erddap_feeder::registry::{load_mmsi_registry, merge_mmsi_lookups};
+use erddap_feeder::schema::{dataset_id, info_url, DatasetSchema};
+use erddap_feeder::spool::{SharedSpool, Spool, SpoolEntry};
+use erddap_feeder::store::{Observation, ObservationQuery, Store};
+use erddap_feeder::validate::{validate_config, ConfigProblem, Severity};
+use erddap_feeder::DecodeError;
+use erddap_feeder::{apply_not_available, columns_as_query_arguments, DecoderRegistry};
+use erddap_feeder::{AcceptedMessage, MMSILookup, NotAvailableMode};
+use erddap_feeder::{AisCatcherMessage, AisMessage, AisMessageIdentifier, AisStationData};
+use erddap_feeder::{AppConfig, ArgsState, CsvOutputConfig, ErddapDestination, ErddapResponse};
+use erddap_feeder::{Column, ErddapSink, InfluxConfig, MessageDecoder};
+use erddap_feeder::{FieldSentinel, FieldSpec, FieldType, STATION_FIELDS};
+use erddap_feeder::{PerMessageConfig, PerSinkConfig, QueryRow, SharedArgsState};
+use erddap_feeder::{DEFAULT_SINK, REDACTED};
+use futures::future::join_all;
 use indoc::printdoc;
 use serde_json::json;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(test)]
+#[path = "testing.rs"]
+mod testing;
 
 const APP_NAME: &str = "erddap-feeder";
 enum Exits {
@@ -17,6 +48,15 @@ enum Exits {
     DefaultMmsiLookup = 5,
     DefaultErddapUrl = 6,
     DefaultErddapKey = 7,
+    CouldNotOpenSpool = 8,
+    CouldNotReadReplayInput = 9,
+    CouldNotOpenCaptureDir = 10,
+    CheckFoundProblems = 11,
+    CouldNotWriteGeneratedFiles = 12,
+    InvalidConfig = 13,
+    CouldNotReadKeyFile = 14,
+    CouldNotReadMmsiRegistry = 15,
+    CouldNotOpenDatabase = 16,
 }
 
 /// Processes JSON AIS weather data emitted from AIS-catcher in HTTP mode, then
@@ -34,25 +74,305 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    Check(Check),
+    GenerateErddap(GenerateErddap),
     Initialize(Initialize),
     Manual(Manual),
+    Replay(Replay),
     Run(Run),
+    ValidateConfig(ValidateConfig),
+}
+
+/// Where the configuration file is: a name in the platform's configuration directory, or a
+/// path to anywhere.
+#[derive(Args, Clone)]
+struct ConfigLocation {
+    /// Alternate configuration file; just the base name without .toml, in the platform's
+    /// configuration directory
+    #[arg(short, long, default_value_t = String::from("default-config"))]
+    config_file: String,
+
+    /// Path to the configuration file, instead of looking it up by name. TOML, YAML and JSON
+    /// are read and written, according to the extension (.toml, .yaml or .yml, .json).
+    #[arg(
+        long,
+        env = "ERDDAP_FEEDER_CONFIG_PATH",
+        conflicts_with = "config_file"
+    )]
+    config_path: Option<PathBuf>,
+}
+
+impl ConfigLocation {
+    /// The full path of the configuration file
+    fn path(&self) -> PathBuf {
+        match &self.config_path {
+            Some(path) => path.clone(),
+            None => get_config_path(&self.config_file),
+        }
+    }
+
+    fn load(&self) -> Result<AppConfig, String> {
+        match &self.config_path {
+            Some(path) => load_config_path(path),
+            None => confy::load(APP_NAME, self.config_file.as_str()).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn store(&self, config: AppConfig) -> Result<(), String> {
+        match &self.config_path {
+            Some(path) => store_config_path(path, &config),
+            None => {
+                confy::store(APP_NAME, self.config_file.as_str(), config).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Settings that override the configuration file, from the command line or the environment.
+/// Lists are comma separated.
+#[derive(Args, Clone, Default)]
+#[command(next_help_heading = "Configuration overrides")]
+struct ConfigOverrides {
+    /// Overrides erddap_url
+    #[arg(long, env = "ERDDAP_FEEDER_ERDDAP_URL")]
+    erddap_url: Option<String>,
+
+    /// Overrides erddap_key. The key is visible to anything that can see the process's
+    /// arguments or environment; --erddap-key-file avoids that.
+    #[arg(long, env = "ERDDAP_FEEDER_ERDDAP_KEY", hide_env_values = true)]
+    erddap_key: Option<String>,
+
+    /// File holding the ERDDAP author key, such as a Docker secret; overrides
+    /// erddap_key_file, and takes precedence over any erddap_key
+    #[arg(long, env = "ERDDAP_FEEDER_ERDDAP_KEY_FILE")]
+    erddap_key_file: Option<PathBuf>,
+
+    /// Overrides accept_invalid_certificates
+    #[arg(long, env = "ERDDAP_FEEDER_ACCEPT_INVALID_CERTIFICATES")]
+    accept_invalid_certificates: Option<bool>,
+
+    /// Overrides publish_fields, such as lat,lon,wspeed
+    #[arg(long, env = "ERDDAP_FEEDER_PUBLISH_FIELDS", value_delimiter = ',')]
+    publish_fields: Option<Vec<String>>,
+
+    /// Overrides rename_fields, as source=target pairs, such as lat=latitude,lon=longitude
+    #[arg(long, env = "ERDDAP_FEEDER_RENAME_FIELDS", value_delimiter = ',', value_parser = parse_rename)]
+    rename_fields: Option<Vec<(String, String)>>,
+
+    /// Overrides not_available: sentinel, missing or omit
+    #[arg(long, env = "ERDDAP_FEEDER_NOT_AVAILABLE", value_parser = parse_not_available)]
+    not_available: Option<NotAvailableMode>,
+
+    /// Overrides message_config, as a JSON array of the same tables the file has
+    #[arg(long, env = "ERDDAP_FEEDER_MESSAGE_CONFIG", value_parser = parse_message_config)]
+    message_config: Option<MessageConfigList>,
+
+    /// Overrides sinks, as a JSON array of the same tables the file has
+    #[arg(long, env = "ERDDAP_FEEDER_SINKS", hide_env_values = true, value_parser = parse_sinks)]
+    sinks: Option<SinkList>,
+
+    /// Overrides mmsi_registry
+    #[arg(long, env = "ERDDAP_FEEDER_MMSI_REGISTRY")]
+    mmsi_registry: Option<PathBuf>,
+
+    /// Overrides jsonl_dir
+    #[arg(long, env = "ERDDAP_FEEDER_JSONL_DIR")]
+    jsonl_dir: Option<PathBuf>,
+
+    /// Overrides csv_output, as a JSON object of the same settings the file's table has
+    #[arg(long, env = "ERDDAP_FEEDER_CSV_OUTPUT", value_parser = parse_csv_output)]
+    csv_output: Option<CsvOutputConfig>,
+
+    /// Overrides influxdb, as a JSON object of the same settings the file's table has
+    #[arg(long, env = "ERDDAP_FEEDER_INFLUXDB", hide_env_values = true, value_parser = parse_influxdb)]
+    influxdb: Option<InfluxConfig>,
+
+    /// Overrides sqlite_path
+    #[arg(long, env = "ERDDAP_FEEDER_SQLITE_PATH")]
+    sqlite_path: Option<PathBuf>,
+
+    /// Overrides mmsi_lookup, as mmsi=station name pairs
+    #[arg(long, env = "ERDDAP_FEEDER_MMSI_LOOKUP", value_delimiter = ',', value_parser = parse_mmsi_lookup)]
+    mmsi_lookup: Option<Vec<MMSILookup>>,
+}
+
+impl ConfigOverrides {
+    /// Replace the settings in the configuration file with any given here.
+    fn apply(&self, cfg: &mut AppConfig) {
+        if let Some(erddap_url) = &self.erddap_url {
+            cfg.erddap_url = erddap_url.clone();
+        }
+        if let Some(erddap_key) = &self.erddap_key {
+            cfg.erddap_key = erddap_key.clone();
+        }
+        if let Some(erddap_key_file) = &self.erddap_key_file {
+            cfg.erddap_key_file = Some(erddap_key_file.clone());
+        }
+        if let Some(accept_invalid_certificates) = self.accept_invalid_certificates {
+            cfg.accept_invalid_certificates = accept_invalid_certificates;
+        }
+        if let Some(publish_fields) = &self.publish_fields {
+            cfg.publish_fields = publish_fields.clone();
+        }
+        if let Some(rename_fields) = &self.rename_fields {
+            cfg.rename_fields = rename_fields.clone();
+        }
+        if let Some(not_available) = self.not_available {
+            cfg.not_available = not_available;
+        }
+        if let Some(message_config) = &self.message_config {
+            cfg.message_config = message_config.clone();
+        }
+        if let Some(mmsi_lookup) = &self.mmsi_lookup {
+            cfg.mmsi_lookup = mmsi_lookup.clone();
+        }
+        if let Some(mmsi_registry) = &self.mmsi_registry {
+            cfg.mmsi_registry = Some(mmsi_registry.clone());
+        }
+        if let Some(sinks) = &self.sinks {
+            cfg.sinks = sinks.clone();
+        }
+        if let Some(jsonl_dir) = &self.jsonl_dir {
+            cfg.jsonl_dir = Some(jsonl_dir.clone());
+        }
+        if let Some(csv_output) = &self.csv_output {
+            cfg.csv_output = Some(csv_output.clone());
+        }
+        if let Some(influxdb) = &self.influxdb {
+            cfg.influxdb = Some(influxdb.clone());
+        }
+        if let Some(sqlite_path) = &self.sqlite_path {
+            cfg.sqlite_path = Some(sqlite_path.clone());
+        }
+    }
+}
+
+fn parse_rename(value: &str) -> Result<(String, String), String> {
+    let (source, target) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not a source=target pair", value))?;
+    Ok((source.trim().to_string(), target.trim().to_string()))
+}
+
+fn parse_mmsi_lookup(value: &str) -> Result<MMSILookup, String> {
+    let (mmsi, station_name) =
+        parse_rename(value).map_err(|_| format!("'{}' is not a mmsi=station name pair", value))?;
+    Ok(MMSILookup {
+        mmsi,
+        station_name,
+        ..Default::default()
+    })
+}
+
+fn parse_not_available(value: &str) -> Result<NotAvailableMode, String> {
+    serde_json::from_value(json!(value)).map_err(|e| e.to_string())
+}
+
+/// The whole message_config list from one JSON value. Named so that clap doesn't treat it as
+/// a list of separate values.
+type MessageConfigList = Vec<AcceptedMessage>;
+
+fn parse_message_config(value: &str) -> Result<MessageConfigList, String> {
+    serde_json::from_str(value).map_err(|e| e.to_string())
+}
+
+/// The whole sinks list from one JSON value, named for the same reason as MessageConfigList.
+type SinkList = Vec<ErddapSink>;
+
+fn parse_sinks(value: &str) -> Result<SinkList, String> {
+    serde_json::from_str(value).map_err(|e| e.to_string())
+}
+
+fn parse_csv_output(value: &str) -> Result<CsvOutputConfig, String> {
+    serde_json::from_str(value).map_err(|e| e.to_string())
+}
+
+fn parse_influxdb(value: &str) -> Result<InfluxConfig, String> {
+    serde_json::from_str(value).map_err(|e| e.to_string())
+}
+
+/// Check the configuration against the ERDDAP datasets it sends to, reporting fields the
+/// feeder sends that a dataset doesn't define, variables a dataset requires that the feeder
+/// never sends, and fields whose type the dataset can't hold.
+#[derive(Args)]
+struct Check {
+    #[command(flatten)]
+    config: ConfigLocation,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+}
+
+/// Generate ERDDAP dataset definitions matching what the configuration sends: a
+/// datasets.xml with an EDDTableFromHttpGet dataset for each ERDDAP URL, and an init.jsonl
+/// for each dataset's fileDir.
+#[derive(Args)]
+struct GenerateErddap {
+    #[command(flatten)]
+    config: ConfigLocation,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+
+    /// Directory to write datasets.xml and <datasetID>/init.jsonl into
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
 }
 
 /// Initialize a configuration file; overwrites any existing configuration of the same name.
 /// The confy crate is used to determine the platform-appropriate directory to store the
-/// configuration file, and it will automatically add `.toml`.
+/// configuration file, and it will automatically add `.toml`. With --config-path, the file
+/// is written there instead, in the format its extension names.
 #[derive(Args)]
 struct Initialize {
-    /// Alternate configuration file to create; just the base name without .toml
-    #[arg(short, long, default_value_t = String::from("default-config"))]
-    config_file: String,
+    #[command(flatten)]
+    config: ConfigLocation,
 }
 
 /// Show a user manual of sorts
 #[derive(Args)]
 struct Manual {}
 
+/// Feed captured AIS-catcher packets through the same processing as the run subcommand,
+/// sending the rows to ERDDAP or, with --dry-run, printing them. Files can hold one JSON
+/// packet per line, or a single packet.
+#[derive(Args)]
+struct Replay {
+    /// Files of captured packets, or directories of such files (read in name order)
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    #[command(flatten)]
+    config: ConfigLocation,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+
+    /// Print the .insert URL for each row, with the author key redacted, instead of sending
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// File to hold rows that ERDDAP could not take, for the next run to retry. Defaults to
+    /// the same spool as the run subcommand, which should not be running at the same time.
+    #[arg(long)]
+    spool_file: Option<PathBuf>,
+
+    /// Most rows to send to ERDDAP in a single .insert request
+    #[arg(long, default_value_t = 100)]
+    batch_max_rows: usize,
+}
+
+/// Check the configuration file and list every problem found, without starting the feeder.
+/// Exits non-zero if any of them would stop the run subcommand starting.
+#[derive(Args)]
+struct ValidateConfig {
+    #[command(flatten)]
+    config: ConfigLocation,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+}
+
 /// Run the HTTP listener to accept JSON packets and send them to ERDDAP
 #[derive(Args)]
 struct Run {
@@ -60,9 +380,11 @@ struct Run {
     #[arg(long, default_value_t = SocketAddr::from(([0,0,0,0], 22022)))]
     bind_address: SocketAddr,
 
-    /// Alternate configuration file to load
-    #[arg(short, long, default_value_t = String::from("default-config"))]
-    config_file: String,
+    #[command(flatten)]
+    config: ConfigLocation,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
 
     /// Dump every received JSON packet (a packet can contain several messages)
     #[arg(long, default_value_t = false)]
@@ -71,6 +393,70 @@ struct Run {
     /// Dump every accepted message's raw structure
     #[arg(long, default_value_t = false)]
     dump_accepted_messages: bool,
+
+    /// File to hold rows that could not be sent to ERDDAP until they can be retried.
+    /// Defaults to a .spool.jsonl file alongside the configuration file.
+    #[arg(long)]
+    spool_file: Option<PathBuf>,
+
+    /// Seconds to wait before the first retry of a spooled row
+    #[arg(long, default_value_t = 5)]
+    retry_initial_backoff: u64,
+
+    /// Upper limit, in seconds, on the wait between retries of a spooled row
+    #[arg(long, default_value_t = 600)]
+    retry_max_backoff: u64,
+
+    /// Most rows to send to ERDDAP in a single .insert request
+    #[arg(long, default_value_t = 100)]
+    batch_max_rows: usize,
+
+    /// Milliseconds to collect rows across packets before sending them as one batch. With
+    /// the default of 0, rows are only batched within a packet.
+    #[arg(long, default_value_t = 0)]
+    batch_window_ms: u64,
+
+    /// Seconds within which ERDDAP must have accepted rows (or answered a probe) for /readyz
+    /// to report ready. With the default of 0, only the most recent outcome counts.
+    #[arg(long, default_value_t = 0)]
+    ready_window: u64,
+
+    /// Seconds between requests for each dataset's .das page, to check ERDDAP is reachable
+    /// even when no rows are being sent. With the default of 0, no probes are made.
+    #[arg(long, default_value_t = 0)]
+    probe_interval: u64,
+
+    /// Directory to record the raw body of every packet posted to /aiscatcher, one packet
+    /// per line, in files that the replay subcommand can read.
+    #[arg(long)]
+    capture_dir: Option<PathBuf>,
+
+    /// Start a new capture file after this many megabytes (before compression); 0 disables
+    #[arg(long, default_value_t = 100)]
+    capture_rotate_mb: u64,
+
+    /// Start a new capture file after this many seconds; 0 disables
+    #[arg(long, default_value_t = 86400)]
+    capture_rotate_interval: u64,
+
+    /// Compress capture files with gzip
+    #[arg(long, default_value_t = false)]
+    capture_gzip: bool,
+
+    /// Number of capture files to keep, deleting the oldest; 0 keeps them all
+    #[arg(long, default_value_t = 0)]
+    capture_keep_files: usize,
+
+    /// Seconds between checks of the modification times of the configuration file and any
+    /// MMSI registry, reloading them when either changes. With the default of 0, the configuration is only reloaded on SIGHUP.
+    #[arg(long, default_value_t = 0)]
+    config_poll_interval: u64,
+
+    /// Process packets as normal, but log the .insert URL for each batch of rows (with the
+    /// author key redacted) instead of sending it. The URLs are also returned to
+    /// AIS-catcher in the response. Nothing is spooled or retried.
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
 }
 
 /// Dispatch the subcommands
@@ -79,197 +465,1116 @@ async fn main() {
     let args = Cli::parse();
     tracing_subscriber::fmt::init();
     match &args.command {
+        Commands::Check(check) => {
+            exec_check(check).await;
+        }
+        Commands::GenerateErddap(generate) => {
+            exec_generate_erddap(generate);
+        }
         Commands::Initialize(init) => {
             exec_init(init).await;
         }
         Commands::Manual(_) => {
             exec_user_manual();
         }
+        Commands::Replay(replay) => {
+            exec_replay(replay).await;
+        }
         Commands::Run(run) => {
             exec_run(run).await;
         }
+        Commands::ValidateConfig(validate) => {
+            exec_validate_config(validate);
+        }
+    }
+}
+
+/// Fetch the schema of every dataset the configuration sends to, and compare it with the
+/// fields the feeder would send there.
+async fn exec_check(args: &Check) {
+    let app_config = load_config(&args.config, &args.overrides);
+    let message_config = build_message_config_lookup(&app_config);
+    let decoders = decoder_registry();
+
+    let (mut datasets, undescribed) = fields_by_dataset(&message_config, &decoders);
+    let mut problem_count = undescribed.len();
+    for ami in undescribed {
+        println!(
+            "{}: no field list for this message type, so it can't be checked",
+            ami
+        );
+    }
+    // The sinks' datasets have to hold what's sent to them too.
+    for sink in &app_config.sinks {
+        let sink_config = build_sink_message_config_lookup(sink, &message_config);
+        datasets.extend(fields_by_dataset(&sink_config, &decoders).0);
+    }
+
+    for dataset in datasets {
+        println!(
+            "Dataset {} (for {})",
+            dataset.url,
+            dataset.message_types.join(", ")
+        );
+        let client = build_client(dataset.accept_invalid_certificates);
+        // The author key goes with every row too.
+        let mut sent: Vec<(String, FieldType)> = vec![("author".to_string(), FieldType::String)];
+        sent.extend(
+            dataset
+                .fields
+                .iter()
+                .map(|(name, spec)| (name.clone(), spec.data_type)),
+        );
+        match fetch_schema(&client, &dataset.url).await {
+            Ok(schema) => {
+                let problems = schema.check(&sent);
+                if problems.is_empty() {
+                    println!("  OK, {} fields checked", sent.len());
+                }
+                for problem in &problems {
+                    println!("  {}", problem);
+                }
+                problem_count += problems.len();
+            }
+            Err(reason) => {
+                println!("  could not fetch the dataset's variables: {}", reason);
+                problem_count += 1;
+            }
+        }
+    }
+    if problem_count > 0 {
+        std::process::exit(Exits::CheckFoundProblems as i32);
+    }
+}
+
+/// Write out a datasets.xml and init.jsonl files derived from the configuration and the
+/// decoders' field descriptions.
+fn exec_generate_erddap(args: &GenerateErddap) {
+    let app_config = load_config(&args.config, &args.overrides);
+    let message_config = build_message_config_lookup(&app_config);
+    let decoders = decoder_registry();
+
+    let (datasets, undescribed) = fields_by_dataset(&message_config, &decoders);
+    for ami in undescribed {
+        tracing::warn!(
+            "{}: no field list for this message type, so its fields are left out",
+            ami
+        );
+    }
+    let mut generated = vec![];
+    for dataset in datasets {
+        let Some(id) = dataset_id(&dataset.url) else {
+            tracing::warn!("{} is not a tabledap URL, skipping it", dataset.url);
+            continue;
+        };
+        let variables = dataset
+            .fields
+            .iter()
+            .map(|(name, spec)| {
+                let sentinel = dataset.sentinels.iter().find(|s| s.field == spec.name);
+                GeneratedVariable::new(name.clone(), *spec, sentinel, app_config.not_available)
+            })
+            .collect();
+        generated.push(GeneratedDataset {
+            dataset_id: id.to_string(),
+            variables,
+        });
+    }
+
+    let mut files = vec![(
+        args.output_dir.join("datasets.xml"),
+        datasets_xml(&generated),
+    )];
+    for dataset in &generated {
+        files.push((
+            args.output_dir.join(&dataset.dataset_id).join("init.jsonl"),
+            dataset.init_jsonl(),
+        ));
+    }
+    for (path, contents) in files {
+        let written = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, contents));
+        match written {
+            Ok(_) => println!("Wrote {}", path.display()),
+            Err(e) => {
+                tracing::error!("Could not write {}: {}", path.display(), e);
+                std::process::exit(Exits::CouldNotWriteGeneratedFiles as i32);
+            }
+        }
+    }
+}
+
+/// The fields the feeder sends to one ERDDAP dataset, across every message type routed there
+struct DatasetFields {
+    url: String,
+    accept_invalid_certificates: bool,
+    message_types: Vec<String>,
+    /// The names ERDDAP sees, with the decoder's description of each field
+    fields: Vec<(String, FieldSpec)>,
+    /// The not available codes of the decoders involved, by decoded field name
+    sentinels: Vec<FieldSentinel>,
+}
+
+/// Work out which fields are sent to each dataset. Message types whose decoder doesn't
+/// describe its fields are returned separately.
+fn fields_by_dataset(
+    message_config: &HashMap<AisMessageIdentifier, PerMessageConfig>,
+    decoders: &DecoderRegistry,
+) -> (Vec<DatasetFields>, Vec<AisMessageIdentifier>) {
+    let mut message_types: Vec<(&AisMessageIdentifier, &PerMessageConfig)> =
+        message_config.iter().collect();
+    message_types.sort_by_key(|(ami, _)| ami.to_string());
+    let mut datasets: Vec<DatasetFields> = vec![];
+    let mut undescribed = vec![];
+    for (ami, pmc) in message_types {
+        let (fields, sentinels) = match decoders.get(ami) {
+            Some(decoder) if !decoder.fields().is_empty() => {
+                (published_fields(pmc, decoder.as_ref()), decoder.sentinels())
+            }
+            _ => {
+                undescribed.push(ami.clone());
+                continue;
+            }
+        };
+        let url = &pmc.destination.url;
+        let index = match datasets.iter().position(|dataset| dataset.url == *url) {
+            Some(index) => index,
+            None => {
+                datasets.push(DatasetFields {
+                    url: url.clone(),
+                    accept_invalid_certificates: pmc.destination.accept_invalid_certificates,
+                    message_types: vec![],
+                    fields: vec![],
+                    sentinels: vec![],
+                });
+                datasets.len() - 1
+            }
+        };
+        let dataset = &mut datasets[index];
+        dataset.message_types.push(ami.to_string());
+        for (name, spec) in fields {
+            if !dataset.fields.iter().any(|(n, _)| *n == name) {
+                dataset.fields.push((name, spec));
+            }
+        }
+        dataset.sentinels.extend(sentinels);
+    }
+    (datasets, undescribed)
+}
+
+/// Fetch the variables of the dataset at a tabledap URL from ERDDAP's info page.
+async fn fetch_schema(client: &reqwest::Client, url: &str) -> Result<DatasetSchema, String> {
+    let info = info_url(url).ok_or_else(|| format!("{} is not a tabledap URL", url))?;
+    let response = client
+        .get(&info)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("{} returned {}", info, response.status()));
+    }
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Could not read {}: {}", info, e))?;
+    DatasetSchema::from_info_json(&body)
+}
+
+/// Report every problem with the configuration file.
+fn exec_validate_config(args: &ValidateConfig) {
+    let cfg_file_name = args.config.path();
+    let app_config = match read_config(&args.config, &args.overrides) {
+        Ok(app_config) => app_config,
+        Err((exit, message)) => {
+            println!("{}", message);
+            std::process::exit(exit as i32);
+        }
+    };
+    let problems = validate_config(&app_config, &decoder_registry());
+    for problem in &problems {
+        println!("{}: {}", problem.severity(), problem);
+    }
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity() == Severity::Error)
+        .count();
+    println!(
+        "{}: {} errors, {} warnings",
+        cfg_file_name.display(),
+        errors,
+        problems.len() - errors
+    );
+    if errors > 0 {
+        std::process::exit(Exits::InvalidConfig as i32);
     }
 }
 
 /// Initialize the configuration file that the feeder will use.
 async fn exec_init(args: &Initialize) {
-    create_config(&args.config_file);
+    create_config(&args.config);
 }
 
 /// Process the configuration file into a state object, then start the webserver,
 /// listen for requests, process them, and send the resulting data to ERDDAP.
 async fn exec_run(args: &Run) {
     // load config
-    let app_config = load_config(&args.config_file);
+    let app_config = load_config(&args.config, &args.overrides);
     tracing::info!("ERDDAP URL: {}", app_config.erddap_url);
     tracing::info!(
         "Accept self-signed certs: {}",
         app_config.accept_invalid_certificates
     );
 
-    // Convert the config.mmsi_lookup vector of objects to a map of name to station id
-    // This enables the station data as_query_arguments function to map the MMSI in the
-    // input to a station name without hardcoding.
-    let mmsi_to_station_id_map = build_mmsi_to_station_id_map(&app_config);
-
-    // Convert the config.message_config vector into a map of AIS message identifier to
-    // ignored MMSIs for that message type.
-    let message_config = build_message_config_lookup(&app_config);
+    let mmsi_registry = app_config.mmsi_registry.clone();
 
-    // Convert the config.rename_fields vector of string tuples to a looup map.
-    let rename_fields_map = build_field_rename_map(&app_config);
+    // Rows that ERDDAP couldn't accept last time around are still waiting in the spool.
+    let spool = open_spool(&args.spool_file, &args.config);
 
     // Axum/tokio can pass a state object to every handler that's invoked. Here, it's
     // used to pass the configuration of the program to every handler (and it must come
     // after the route).
     let args_state = ArgsState {
-        url: app_config.erddap_url,
-        author_key: app_config.erddap_key,
-        accept_invalid_certificates: app_config.accept_invalid_certificates,
-        publish_fields: app_config.publish_fields,
-        rename_fields: rename_fields_map,
         dump_all_packets: args.dump_all_packets,
         dump_accepted_messages: args.dump_accepted_messages,
-        mmsi_lookup: mmsi_to_station_id_map,
-        message_config_lookup: message_config,
+        batch_window: Duration::from_millis(args.batch_window_ms),
+        batch_max_rows: args.batch_max_rows.max(1),
+        ready_window: Duration::from_secs(args.ready_window),
+        capture: open_capture(args).map(|capture| Arc::new(Mutex::new(capture))),
+        dry_run: args.dry_run,
+        ..build_args_state(app_config, spool)
     };
+    let capture = args_state.capture.clone();
+    let batch_window = args_state.batch_window;
+    let shared_state = SharedArgsState::new(args_state);
+
+    if !batch_window.is_zero() {
+        tokio::spawn(run_batch_flusher(shared_state.clone(), batch_window));
+    }
+
+    tokio::spawn(run_config_reloader(
+        shared_state.clone(),
+        args.config.clone(),
+        mmsi_registry,
+        args.overrides.clone(),
+        Duration::from_secs(args.config_poll_interval),
+    ));
+
+    if args.dry_run {
+        tracing::warn!("Dry run: nothing will be sent to ERDDAP");
+    } else {
+        if args.probe_interval > 0 {
+            tokio::spawn(run_erddap_probe(
+                shared_state.clone(),
+                Duration::from_secs(args.probe_interval),
+            ));
+        }
+
+        // Replay anything in the spool in the background, and keep retrying as rows are
+        // added.
+        tokio::spawn(run_retry_worker(
+            shared_state.clone(),
+            Duration::from_secs(args.retry_initial_backoff.max(1)),
+            Duration::from_secs(args.retry_max_backoff.max(1)),
+        ));
+    }
 
     // Start a router for the POST requests that AIS-catcher sends.
     let app = Router::new()
         .route("/aiscatcher", post(process_aiscatcher_submission))
-        .with_state(args_state);
+        .route("/metrics", get(serve_metrics))
+        .route("/healthz", get(serve_healthz))
+        .route("/readyz", get(serve_readyz))
+        .route("/observations", get(serve_observations))
+        .with_state(shared_state.clone());
 
     tracing::info!("Listening on {}", args.bind_address);
     // Let's go!
     axum::Server::bind(&args.bind_address)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
-}
-
-/// Convert the TOMLified table of mmsi to name into a map for rapid lookups.
-fn build_mmsi_to_station_id_map(app_config: &AppConfig) -> HashMap<String, String> {
-    let mut mmsi_to_station_id_map = HashMap::new();
-
-    for entry in &app_config.mmsi_lookup {
-        tracing::info!(
-            "MMSI lookups - mapped MMSI '{}' to '{}'",
-            entry.mmsi,
-            entry.station_name
-        );
-        mmsi_to_station_id_map.insert(entry.mmsi.clone(), entry.station_name.clone());
-    }
 
-    mmsi_to_station_id_map
-}
-
-fn build_field_rename_map(app_config: &AppConfig) -> HashMap<String, String> {
-    let mut renames: HashMap<String, String> = HashMap::new();
+    // Rows waiting for the batch window would otherwise be lost.
+    spool_pending_rows(&shared_state.current()).await;
 
-    for (source, target) in &app_config.rename_fields {
-        tracing::info!("Field renames - mapped source '{}' to '{}'", source, target);
-        renames.insert(source.clone(), target.clone());
+    // A gzipped capture file can't be read to the end unless it's finished properly.
+    if let Some(capture) = capture {
+        capture.lock().unwrap().close();
     }
-
-    renames
 }
 
-/// Convert the TOMLified table of accepted packets into something that can be matched
-fn build_message_config_lookup(
-    app_config: &AppConfig,
-) -> HashMap<AisMessageIdentifier, PerMessageConfig> {
-    let mut lookup = HashMap::new();
-    for entry in &app_config.message_config {
-        let ami = AisMessageIdentifier {
-            r#type: entry.r#type,
-            dac: entry.dac,
-            fid: entry.fid,
-        };
-        let pmc = PerMessageConfig {
-            ignore_mmsi: entry.ignore_mmsi.clone(),
-        };
-        tracing::info!("Ignore list - mapped {} to {:?}", ami, entry.ignore_mmsi);
-        lookup.insert(ami, pmc);
+/// Resolve when the process is asked to stop, by Ctrl-C or by the SIGTERM that docker stop
+/// sends.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Could not listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Could not listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
-    lookup
+    tracing::info!("Shutting down");
 }
 
-/// Get the on-disk filename for a config file
-fn get_config_path(config_file: &String) -> String {
-    // Knowing the file name is useful for the rest of the error messages.
-    let cfg_file = match confy::get_configuration_file_path("erddap-feeder", config_file.as_str()) {
-        Ok(buf) => buf,
-        Err(error) => {
-            tracing::error!("Could not get configuration file name: {}", error);
-            std::process::exit(Exits::CouldNotGetConfigFilePath as i32);
-        }
+/// Replay captured packets through the pipeline, one packet at a time, as though
+/// AIS-catcher had just posted them.
+async fn exec_replay(args: &Replay) {
+    let app_config = load_config(&args.config, &args.overrides);
+    let spool = open_spool(&args.spool_file, &args.config);
+    let args_state = ArgsState {
+        batch_max_rows: args.batch_max_rows.max(1),
+        ..build_args_state(app_config, spool)
     };
-    cfg_file.into_os_string().into_string().unwrap()
-}
-
-/// Load a configuration file from the OS config dir location. If no config is present,
-/// write a default configuration
-fn load_config(config_file: &String) -> AppConfig {
-    let cfg_file_name = get_config_path(&config_file);
 
-    // Attempt loading the configuration file; it can not exist, and confy will not
-    // consider that to be an error.
-    let cfg: AppConfig = match confy::load(APP_NAME, config_file.as_str()) {
-        Ok(config) => config,
+    let files = match replay_files(&args.paths) {
+        Ok(files) => files,
         Err(error) => {
-            tracing::error!(
-                "Could not load configuration file {}: {}",
-                cfg_file_name,
-                error
-            );
-            std::process::exit(Exits::CouldNotLoadConfigFile as i32);
+            tracing::error!("Could not list the files to replay: {}", error);
+            std::process::exit(Exits::CouldNotReadReplayInput as i32);
         }
     };
-
-    // Empty vector means triggering the creation of a default configuration file.
-    if cfg.mmsi_lookup.is_empty() {
-        tracing::error!(
-            "The configuration file {} does not have any MMSI lookups defined.",
-            cfg_file_name
+    let mut packet_count = 0;
+    let mut totals = PacketSummary::default();
+    for file in files {
+        let packets = match read_packets(&file) {
+            Ok(packets) => packets,
+            Err(error) => {
+                tracing::error!("Could not read {}: {}", file.display(), error);
+                std::process::exit(Exits::CouldNotReadReplayInput as i32);
+            }
+        };
+        tracing::info!(
+            "Replaying {} packets from {}",
+            packets.len(),
+            file.display()
         );
-        std::process::exit(Exits::EmptyMmsiLookup as i32);
-    } else {
-        // The vector of mmsi lookups was not empty, but is the default present? If so,
-        // the user needs to edit the file and set up the lookup properly.
-        for lookup in &cfg.mmsi_lookup {
-            if lookup.mmsi == DEFAULT_MMSI {
-                tracing::error!(
-                    "The configuration file {} has the default MMSI lookup. Please edit the file.",
-                    cfg_file_name
+        for packet in packets {
+            packet_count += 1;
+            let mut summary = convert_packet(&args_state, &packet);
+            for (index, error) in &summary.rejected {
+                tracing::warn!(
+                    "Rejected message {} of packet {}: {}",
+                    index,
+                    packet_count,
+                    error
+                );
+            }
+            totals.total += summary.total;
+            totals.processed += summary.processed;
+            totals.skipped += summary.skipped;
+            totals.ignored += summary.ignored;
+            totals.rejected.append(&mut summary.rejected);
+            if args.dry_run {
+                for (url, row) in &summary.rows {
+                    println!("{}", redacted_insert_url(url, row));
+                }
+            } else {
+                tokio::join!(
+                    flush_rows(&args_state, summary.rows),
+                    write_to_influxdb(&args_state, summary.influx_lines),
+                    write_history(&args_state, summary.observations)
                 );
-                std::process::exit(Exits::DefaultMmsiLookup as i32);
             }
         }
-        if cfg.erddap_url == DEFAULT_URL {
-            tracing::error!(
-                "The configuration file {} has the default ERDDAP URL. Please edit the file.",
-                cfg_file_name
+    }
+    tracing::info!("Replayed {} packets. {}", packet_count, totals.describe());
+    for (sink, spool) in args_state.spools() {
+        let spool = spool.lock();
+        if !spool.is_empty() {
+            tracing::warn!(
+                "Some rows could not be sent to {} and will be retried by the next run; {}",
+                sink,
+                spool.describe()
             );
-            std::process::exit(Exits::DefaultErddapUrl as i32);
         }
-        if cfg.erddap_key == DEFAULT_KEY {
+    }
+}
+
+/// Expand the replay arguments into a list of files; the files in a directory are taken in
+/// name order, which is time order for captures.
+fn replay_files(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = vec![];
+            for entry in std::fs::read_dir(path)? {
+                let entry_path = entry?.path();
+                if entry_path.is_file() {
+                    entries.push(entry_path);
+                }
+            }
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Read the AIS-catcher packets from a file. A file is either a single packet (which may be
+/// pretty-printed over several lines) or one packet per line; lines that can't be read as a
+/// packet are logged and skipped. Gzipped capture files are decompressed.
+fn read_packets(path: &Path) -> std::io::Result<Vec<AisCatcherMessage>> {
+    let mut bytes = vec![];
+    if let Err(error) = open_capture_file(path)?.read_to_end(&mut bytes) {
+        // A capture file that was being written when the feeder stopped can end part way
+        // through a gzip stream; everything before that point is still good.
+        if error.kind() != std::io::ErrorKind::UnexpectedEof {
+            return Err(error);
+        }
+        tracing::warn!("{} ends unexpectedly, reading what's there", path.display());
+    }
+    let contents = String::from_utf8_lossy(&bytes);
+    if let Ok(packet) = serde_json::from_str::<AisCatcherMessage>(&contents) {
+        return Ok(vec![packet]);
+    }
+    let mut packets = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<AisCatcherMessage>(line) {
+            Ok(packet) => packets.push(packet),
+            Err(error) => tracing::warn!(
+                "Skipping line {} of {}, it is not an AIS-catcher packet: {}",
+                number + 1,
+                path.display(),
+                error
+            ),
+        }
+    }
+    Ok(packets)
+}
+
+/// The decoders every subcommand converts messages with. This binary only knows the
+/// built-in ones; a build with decoders of its own registers them here, and they're then
+/// used for running, replaying, checking and generating alike.
+fn decoder_registry() -> DecoderRegistry {
+    DecoderRegistry::with_builtin_decoders()
+}
+
+/// Turn the configuration into the state shared by everything that processes packets.
+/// Settings that only the run subcommand has are given their defaults.
+fn build_args_state(app_config: AppConfig, spool: Spool) -> ArgsState {
+    // Convert the config.mmsi_lookup vector of objects to a map of name to station id
+    // This enables the station data as_query_arguments function to map the MMSI in the
+    // input to a station name without hardcoding.
+    let mmsi_to_station_id_map = build_mmsi_to_station_id_map(&app_config);
+    let stations = build_station_map(&app_config);
+
+    // Convert the config.message_config vector into a map of AIS message identifier to
+    // ignored MMSIs, destination and field handling for that message type.
+    let message_config = build_message_config_lookup(&app_config);
+
+    let sinks = match build_sinks(&app_config, &message_config, spool.path(), &[]) {
+        Ok(sinks) => sinks,
+        Err(error) => {
+            tracing::error!("{}", error);
+            std::process::exit(Exits::CouldNotOpenSpool as i32);
+        }
+    };
+    let outputs = build_outputs(&app_config, &message_config);
+    let store = match open_store(&app_config, None) {
+        Ok(store) => store,
+        Err(error) => {
+            tracing::error!("{}", error);
+            std::process::exit(Exits::CouldNotOpenDatabase as i32);
+        }
+    };
+    let mut health = ErddapHealth::new();
+    health.set_sinks(&sink_names(&sinks));
+
+    ArgsState {
+        url: app_config.erddap_url,
+        author_key: app_config.erddap_key,
+        accept_invalid_certificates: app_config.accept_invalid_certificates,
+        dump_all_packets: false,
+        dump_accepted_messages: false,
+        mmsi_lookup: mmsi_to_station_id_map,
+        stations,
+        message_config_lookup: message_config,
+        sinks,
+        spool: SharedSpool::new(spool),
+        outputs,
+        influxdb: app_config.influxdb,
+        store,
+        pending_rows: Arc::new(Mutex::new(vec![])),
+        batch_window: Duration::ZERO,
+        batch_max_rows: 100,
+        decoders: decoder_registry(),
+        not_available: app_config.not_available,
+        metrics: Metrics::new(),
+        health: Arc::new(Mutex::new(health)),
+        ready_window: Duration::ZERO,
+        capture: None,
+        dry_run: false,
+    }
+}
+
+/// The local files the default sink's rows are also written to.
+fn build_outputs(
+    app_config: &AppConfig,
+    message_config: &HashMap<AisMessageIdentifier, PerMessageConfig>,
+) -> Vec<Arc<dyn RowOutput>> {
+    let mut outputs: Vec<Arc<dyn RowOutput>> = vec![];
+    if let Some(dir) = &app_config.jsonl_dir {
+        tracing::info!("Writing rows to JSONL files in {}", dir.display());
+        outputs.push(Arc::new(JsonlOutput::new(dir)));
+    }
+    if let Some(csv_output) = &app_config.csv_output {
+        tracing::info!("Writing rows to CSV files in {}", csv_output.dir.display());
+        // The CSV header is every field published to the dataset, so that it doesn't
+        // change with the fields each row happens to have.
+        let decoders = decoder_registry();
+        let (datasets, _) = fields_by_dataset(message_config, &decoders);
+        let columns = datasets
+            .into_iter()
+            .map(|dataset| {
+                let names = dataset.fields.into_iter().map(|(name, _)| name).collect();
+                (dataset.url, names)
+            })
+            .collect();
+        outputs.push(Arc::new(CsvOutput::new(
+            &csv_output.dir,
+            csv_output.rotation,
+            columns,
+        )));
+    }
+    outputs
+}
+
+/// Open the database the history is kept in, if there is one. The current one is kept when
+/// the configuration is reloaded without changing its path.
+fn open_store(
+    app_config: &AppConfig,
+    current: Option<&Arc<Store>>,
+) -> Result<Option<Arc<Store>>, String> {
+    let Some(path) = &app_config.sqlite_path else {
+        return Ok(None);
+    };
+    if let Some(store) = current.filter(|store| store.path() == path) {
+        return Ok(Some(store.clone()));
+    }
+    let store = Store::open(path)
+        .map_err(|e| format!("Could not open database {}: {}", path.display(), e))?;
+    tracing::info!("Keeping a history of messages in {}", path.display());
+    Ok(Some(Arc::new(store)))
+}
+
+/// The mmsi_lookup entries by MMSI, for the station details beyond the name.
+fn build_station_map(app_config: &AppConfig) -> HashMap<String, MMSILookup> {
+    app_config
+        .mmsi_lookup
+        .iter()
+        .map(|entry| (entry.mmsi.clone(), entry.clone()))
+        .collect()
+}
+
+/// Convert the TOMLified table of mmsi to name into a map for rapid lookups.
+fn build_mmsi_to_station_id_map(app_config: &AppConfig) -> HashMap<String, String> {
+    let mut mmsi_to_station_id_map = HashMap::new();
+
+    for entry in &app_config.mmsi_lookup {
+        tracing::info!(
+            "MMSI lookups - mapped MMSI '{}' to '{}'",
+            entry.mmsi,
+            entry.station_name
+        );
+        mmsi_to_station_id_map.insert(entry.mmsi.clone(), entry.station_name.clone());
+    }
+
+    mmsi_to_station_id_map
+}
+
+/// Convert a list of (source, target) field name pairs into a map for rapid lookups.
+fn build_field_rename_map(
+    ami: &AisMessageIdentifier,
+    rename_fields: &[(String, String)],
+) -> HashMap<String, String> {
+    let mut renames: HashMap<String, String> = HashMap::new();
+
+    for (source, target) in rename_fields {
+        tracing::info!(
+            "Field renames - {} mapped source '{}' to '{}'",
+            ami,
+            source,
+            target
+        );
+        renames.insert(source.clone(), target.clone());
+    }
+
+    renames
+}
+
+/// Convert the TOMLified table of accepted packets into something that can be matched
+fn build_message_config_lookup(
+    app_config: &AppConfig,
+) -> HashMap<AisMessageIdentifier, PerMessageConfig> {
+    let mut lookup = HashMap::new();
+    for entry in &app_config.message_config {
+        let ami = AisMessageIdentifier {
+            r#type: entry.r#type,
+            dac: entry.dac,
+            fid: entry.fid,
+        };
+        // Message types without a dataset of their own share the global one.
+        let destination = ErddapDestination {
+            sink: DEFAULT_SINK.to_string(),
+            url: entry
+                .erddap_url
+                .clone()
+                .unwrap_or_else(|| app_config.erddap_url.clone()),
+            author_key: entry
+                .erddap_key
+                .clone()
+                .unwrap_or_else(|| app_config.erddap_key.clone()),
+            accept_invalid_certificates: app_config.accept_invalid_certificates,
+        };
+        tracing::info!("Ignore list - mapped {} to {:?}", ami, entry.ignore_mmsi);
+        tracing::info!("Destination - mapped {} to {}", ami, destination.url);
+        // The global field lists are the defaults for message types that don't have
+        // their own.
+        let publish_fields = entry
+            .publish_fields
+            .clone()
+            .unwrap_or_else(|| app_config.publish_fields.clone());
+        tracing::info!("Publish fields - mapped {} to {:?}", ami, publish_fields);
+        let rename_fields = build_field_rename_map(
+            &ami,
+            entry
+                .rename_fields
+                .as_ref()
+                .unwrap_or(&app_config.rename_fields),
+        );
+        let pmc = PerMessageConfig {
+            ignore_mmsi: entry.ignore_mmsi.clone(),
+            destination,
+            publish_fields,
+            rename_fields,
+        };
+        lookup.insert(ami, pmc);
+    }
+    lookup
+}
+
+/// Work out a sink's settings for each accepted message type from the default sink's. Every
+/// message type goes to the sink's one dataset; the sink's field lists, where it has them,
+/// replace those of the message type.
+fn build_sink_message_config_lookup(
+    sink: &ErddapSink,
+    message_config: &HashMap<AisMessageIdentifier, PerMessageConfig>,
+) -> HashMap<AisMessageIdentifier, PerMessageConfig> {
+    let destination = ErddapDestination {
+        sink: sink.name.clone(),
+        url: sink.erddap_url.clone(),
+        author_key: sink.erddap_key.clone(),
+        accept_invalid_certificates: sink.accept_invalid_certificates,
+    };
+    tracing::info!("Sink {} - sending to {}", sink.name, destination.url);
+    message_config
+        .iter()
+        .map(|(ami, pmc)| {
+            let rename_fields = match &sink.rename_fields {
+                Some(rename_fields) => build_field_rename_map(ami, rename_fields),
+                None => pmc.rename_fields.clone(),
+            };
+            let sink_pmc = PerMessageConfig {
+                ignore_mmsi: pmc.ignore_mmsi.clone(),
+                destination: destination.clone(),
+                publish_fields: sink
+                    .publish_fields
+                    .clone()
+                    .unwrap_or_else(|| pmc.publish_fields.clone()),
+                rename_fields,
+            };
+            (ami.clone(), sink_pmc)
+        })
+        .collect()
+}
+
+/// Set up the sinks every accepted row is also sent to. Each has a retry spool of its own
+/// next to the default sink's, so that one sink being unavailable doesn't hold up the
+/// others. Spools that are already open are kept.
+fn build_sinks(
+    app_config: &AppConfig,
+    message_config: &HashMap<AisMessageIdentifier, PerMessageConfig>,
+    spool_path: &Path,
+    current: &[PerSinkConfig],
+) -> Result<Vec<PerSinkConfig>, String> {
+    let mut sinks = vec![];
+    for sink in &app_config.sinks {
+        let spool = match current.iter().find(|s| s.name == sink.name) {
+            Some(existing) => existing.spool.clone(),
+            None => {
+                let path = sink_spool_path(spool_path, &sink.name);
+                let spool = Spool::open(&path).map_err(|error| {
+                    format!("Could not open retry spool {}: {}", path.display(), error)
+                })?;
+                tracing::info!("Retry spool {}: {}", path.display(), spool.describe());
+                SharedSpool::new(spool)
+            }
+        };
+        sinks.push(PerSinkConfig {
+            name: sink.name.clone(),
+            message_config_lookup: build_sink_message_config_lookup(sink, message_config),
+            spool,
+        });
+    }
+    Ok(sinks)
+}
+
+/// The names of the default sink and the others, as readiness knows them
+fn sink_names(sinks: &[PerSinkConfig]) -> Vec<&str> {
+    std::iter::once(DEFAULT_SINK)
+        .chain(sinks.iter().map(|sink| sink.name.as_str()))
+        .collect()
+}
+
+/// The retry spool for a sink: the default sink's, with the sink's name before the
+/// extension.
+fn sink_spool_path(spool_path: &Path, sink: &str) -> PathBuf {
+    let mut file_name = spool_path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(sink);
+    if let Some(extension) = spool_path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    spool_path.with_file_name(file_name)
+}
+
+/// Open the retry spool, defaulting to a file next to the configuration file so that it
+/// lands on the same (persistent) volume.
+fn open_spool(spool_file: &Option<PathBuf>, config: &ConfigLocation) -> Spool {
+    let path = match spool_file {
+        Some(path) => path.clone(),
+        None => config.path().with_extension("spool.jsonl"),
+    };
+    match Spool::open(&path) {
+        Ok(spool) => {
+            tracing::info!("Retry spool {}: {}", path.display(), spool.describe());
+            spool
+        }
+        Err(error) => {
+            tracing::error!("Could not open retry spool {}: {}", path.display(), error);
+            std::process::exit(Exits::CouldNotOpenSpool as i32);
+        }
+    }
+}
+
+/// Set up packet capture, if a capture directory was given.
+fn open_capture(args: &Run) -> Option<Capture> {
+    let dir = args.capture_dir.clone()?;
+    let options = CaptureOptions {
+        dir,
+        rotate_bytes: args.capture_rotate_mb * 1_000_000,
+        rotate_interval: Duration::from_secs(args.capture_rotate_interval),
+        gzip: args.capture_gzip,
+        keep_files: args.capture_keep_files,
+    };
+    match Capture::new(options.clone()) {
+        Ok(capture) => {
+            tracing::info!("Capturing packets in {}", options.dir.display());
+            Some(capture)
+        }
+        Err(error) => {
             tracing::error!(
-                "The configuration file {} has the default ERDDAP key. Please edit the file.",
-                cfg_file_name
+                "Could not open capture directory {}: {}",
+                options.dir.display(),
+                error
             );
-            std::process::exit(Exits::DefaultErddapKey as i32);
+            std::process::exit(Exits::CouldNotOpenCaptureDir as i32);
+        }
+    }
+}
+
+/// Get the on-disk filename for a config file
+fn get_config_path(config_file: &str) -> PathBuf {
+    // Knowing the file name is useful for the rest of the error messages.
+    match confy::get_configuration_file_path("erddap-feeder", config_file) {
+        Ok(buf) => buf,
+        Err(error) => {
+            tracing::error!("Could not get configuration file name: {}", error);
+            std::process::exit(Exits::CouldNotGetConfigFilePath as i32);
+        }
+    }
+}
+
+/// Load a configuration file from the OS config dir location. If no config is present,
+/// write a default configuration
+fn load_config(config: &ConfigLocation, overrides: &ConfigOverrides) -> AppConfig {
+    match try_load_config(config, overrides) {
+        Ok(cfg) => cfg,
+        Err((exit, message)) => {
+            tracing::error!("{}", message);
+            std::process::exit(exit as i32);
+        }
+    }
+}
+
+/// Load and check a configuration file, returning why it can't be used (and the exit code
+/// that goes with the reason) rather than exiting, so that a reload can keep the running
+/// configuration instead. Warnings are logged; the configuration is still used.
+fn try_load_config(
+    config: &ConfigLocation,
+    overrides: &ConfigOverrides,
+) -> Result<AppConfig, (Exits, String)> {
+    let cfg = read_config(config, overrides)?;
+    let problems = validate_config(&cfg, &decoder_registry());
+    let mut errors = vec![];
+    for problem in &problems {
+        match problem.severity() {
+            Severity::Warning => tracing::warn!("Configuration: {}", problem),
+            Severity::Error => errors.push(problem),
+        }
+    }
+    let Some(first) = errors.first() else {
+        return Ok(cfg);
+    };
+    // The placeholder settings from a freshly generated file keep their own exit codes.
+    let exit = match first {
+        ConfigProblem::EmptyMmsiLookup => Exits::EmptyMmsiLookup,
+        ConfigProblem::DefaultMmsiLookup => Exits::DefaultMmsiLookup,
+        ConfigProblem::DefaultErddapUrl => Exits::DefaultErddapUrl,
+        ConfigProblem::DefaultErddapKey(_) => Exits::DefaultErddapKey,
+        _ => Exits::InvalidConfig,
+    };
+    let reasons: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+    Err((
+        exit,
+        format!(
+            "The configuration file {} needs editing: {}",
+            config.path().display(),
+            reasons.join("; ")
+        ),
+    ))
+}
+
+/// Read a configuration file and apply the overrides, without checking what's in it.
+fn read_config(
+    config: &ConfigLocation,
+    overrides: &ConfigOverrides,
+) -> Result<AppConfig, (Exits, String)> {
+    // Attempt loading the configuration file; when it's looked up by name it can not
+    // exist, and confy will not consider that to be an error.
+    let mut cfg = config.load().map_err(|error| {
+        (
+            Exits::CouldNotLoadConfigFile,
+            format!(
+                "Could not load configuration file {}: {}",
+                config.path().display(),
+                error
+            ),
+        )
+    })?;
+    overrides.apply(&mut cfg);
+    let key_file_error = |key_file: &Path, error: std::io::Error| {
+        (
+            Exits::CouldNotReadKeyFile,
+            format!(
+                "Could not read ERDDAP key file {}: {}",
+                key_file.display(),
+                error
+            ),
+        )
+    };
+    if let Some(key_file) = &cfg.erddap_key_file {
+        cfg.erddap_key = read_key_file(key_file).map_err(|e| key_file_error(key_file, e))?;
+    }
+    for sink in &mut cfg.sinks {
+        if let Some(key_file) = &sink.erddap_key_file {
+            sink.erddap_key = read_key_file(key_file).map_err(|e| key_file_error(key_file, e))?;
+        }
+    }
+    if let Some(influxdb) = &mut cfg.influxdb {
+        if let Some(token_file) = &influxdb.token_file {
+            influxdb.token = read_key_file(token_file).map_err(|error| {
+                (
+                    Exits::CouldNotReadKeyFile,
+                    format!(
+                        "Could not read InfluxDB token file {}: {}",
+                        token_file.display(),
+                        error
+                    ),
+                )
+            })?;
+        }
+    }
+    if let Some(registry) = &cfg.mmsi_registry {
+        let entries = load_mmsi_registry(registry).map_err(|error| {
+            (
+                Exits::CouldNotReadMmsiRegistry,
+                format!(
+                    "Could not read MMSI registry {}: {}",
+                    registry.display(),
+                    error
+                ),
+            )
+        })?;
+        cfg.mmsi_lookup = merge_mmsi_lookups(&cfg.mmsi_lookup, entries);
+    }
+    Ok(cfg)
+}
+
+/// Read an author key from a file, ignoring the line break editors (and secret managers)
+/// like to leave at the end.
+fn read_key_file(path: &Path) -> std::io::Result<String> {
+    let key = std::fs::read_to_string(path)?.trim().to_string();
+    if key.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the file is empty",
+        ));
+    }
+    Ok(key)
+}
+
+/// Reload the configuration on SIGHUP and, with a non-zero `poll_interval`, whenever the
+/// modification time of the file or of the MMSI registry it names changes. A configuration
+/// that fails the same checks as at startup is logged and the running one kept.
+async fn run_config_reloader(
+    shared: SharedArgsState,
+    config: ConfigLocation,
+    mut mmsi_registry: Option<PathBuf>,
+    overrides: ConfigOverrides,
+    poll_interval: Duration,
+) {
+    let path = config.path();
+    let modified = |path: &Path, registry: &Option<PathBuf>| {
+        let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        (mtime(path), registry.as_deref().and_then(mtime))
+    };
+    let mut last_modified = modified(&path, &mmsi_registry);
+    // interval() can't take zero; the tick is ignored in that case anyway.
+    let mut poll = tokio::time::interval(poll_interval.max(Duration::from_secs(1)));
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Could not listen for SIGHUP");
+    loop {
+        #[cfg(unix)]
+        let hangup_received = hangup.recv();
+        #[cfg(not(unix))]
+        let hangup_received = std::future::pending::<Option<()>>();
+        let reason = tokio::select! {
+            _ = hangup_received => "SIGHUP received",
+            _ = poll.tick(), if !poll_interval.is_zero() => {
+                if modified(&path, &mmsi_registry) == last_modified {
+                    continue;
+                }
+                "configuration file or MMSI registry changed"
+            }
+        };
+        last_modified = modified(&path, &mmsi_registry);
+        tracing::info!(
+            "Reloading configuration from {}: {}",
+            path.display(),
+            reason
+        );
+        match try_load_config(&config, &overrides) {
+            Ok(app_config) => {
+                if app_config.mmsi_registry != mmsi_registry {
+                    mmsi_registry = app_config.mmsi_registry.clone();
+                    last_modified = modified(&path, &mmsi_registry);
+                }
+                match reload_args_state(&shared.current(), app_config) {
+                    Ok(new_state) => {
+                        new_state.health.lock().unwrap().record_config_load(None);
+                        shared.replace(new_state);
+                        tracing::info!("Configuration reloaded");
+                    }
+                    Err(message) => {
+                        tracing::error!("{}; keeping the current configuration", message);
+                        shared
+                            .current()
+                            .health
+                            .lock()
+                            .unwrap()
+                            .record_config_load(Some(message));
+                    }
+                }
+            }
+            Err((_, message)) => {
+                tracing::error!("{}; keeping the current configuration", message);
+                shared
+                    .current()
+                    .health
+                    .lock()
+                    .unwrap()
+                    .record_config_load(Some(message));
+            }
         }
     }
+}
 
-    cfg
+/// Apply a reloaded configuration to the running state. Everything that doesn't come from
+/// the configuration file - the spools, pending rows, metrics, health, capture and command
+/// line settings - carries over. Fails if a new sink's retry spool can't be opened.
+fn reload_args_state(current: &ArgsState, app_config: AppConfig) -> Result<ArgsState, String> {
+    let message_config = build_message_config_lookup(&app_config);
+    let spool_path = current.spool.lock().path().to_path_buf();
+    let sinks = build_sinks(&app_config, &message_config, &spool_path, &current.sinks)?;
+    // The spool of a removed sink stays on disk, for the sink to pick up again if it's put
+    // back; nothing sends its rows meanwhile.
+    for removed in current
+        .sinks
+        .iter()
+        .filter(|old| !sinks.iter().any(|sink| sink.name == old.name))
+    {
+        let spool = removed.spool.lock();
+        if !spool.is_empty() {
+            tracing::warn!(
+                "Sink {} was removed; its rows are left in {} until it's configured again; {}",
+                removed.name,
+                spool.path().display(),
+                spool.describe()
+            );
+        }
+    }
+    let outputs = build_outputs(&app_config, &message_config);
+    let store = open_store(&app_config, current.store.as_ref())?;
+    // A sink that's been removed shouldn't keep the feeder from being ready.
+    current
+        .health
+        .lock()
+        .unwrap()
+        .set_sinks(&sink_names(&sinks));
+    Ok(ArgsState {
+        mmsi_lookup: build_mmsi_to_station_id_map(&app_config),
+        stations: build_station_map(&app_config),
+        message_config_lookup: message_config,
+        sinks,
+        outputs,
+        influxdb: app_config.influxdb,
+        store,
+        url: app_config.erddap_url,
+        author_key: app_config.erddap_key,
+        accept_invalid_certificates: app_config.accept_invalid_certificates,
+        not_available: app_config.not_available,
+        ..current.clone()
+    })
 }
 
 /// Write a default configuration file out, and ask the user to edit it.
-fn create_config(config_file: &String) {
-    let cfg_file_name = get_config_path(&config_file);
+fn create_config(config: &ConfigLocation) {
+    let cfg_file_name = config.path().display().to_string();
     let basic_config = AppConfig::default();
-    match confy::store("erddap-feeder", config_file.as_str(), basic_config) {
-        Ok(_) => tracing::info!("Wrote initial configuration file {}. Please edit it and adjust the [[mmsi_lookup]] entries.", cfg_file_name),
+    match config.store(basic_config) {
+        Ok(_) => tracing::info!("Wrote initial configuration file {}. Please edit it and adjust the mmsi_lookup entries.", cfg_file_name),
         Err(error) => {
             tracing::error!("Could not create configuration file {}: {}", cfg_file_name, error);
             std::process::exit(Exits::CouldNotCreateConfigFile as i32);
@@ -277,61 +1582,263 @@ fn create_config(config_file: &String) {
     };
 }
 
-async fn process_aiscatcher_submission(
-    State(args): State<ArgsState>,
-    Json(payload): Json<AisCatcherMessage>,
-) -> impl IntoResponse {
-    if args.dump_all_packets {
-        tracing::info!("{:?}", payload);
+/// What became of a single message from a packet
+enum MessageOutcome {
+    /// Converted into a row for each sink, to be sent to the dataset at the URL, and a point
+    /// for InfluxDB and an entry in the history if they're kept
+    Accepted {
+        rows: Vec<(String, QueryRow)>,
+        influx_line: Option<String>,
+        observation: Option<Observation>,
+    },
+    /// From an MMSI on the ignore list for its message type
+    Ignored,
+    /// Not a message type the configuration accepts, or one without a decoder
+    Skipped,
+}
+
+/// Decide whether a message is wanted, and if so convert it into a row for ERDDAP.
+fn convert_message(args: &ArgsState, msg: &AisMessage) -> Result<MessageOutcome, DecodeError> {
+    let ami = AisMessageIdentifier::try_from(msg)?;
+    // Is the message identifier allowed by the TOML setup?
+    let Some(pmc) = args.message_config_lookup.get(&ami) else {
+        tracing::debug!("Ignoring message with identifier {}", ami);
+        return Ok(MessageOutcome::Skipped);
+    };
+    tracing::info!("Message meets acceptance criteria {}, converting it", ami);
+    if args.dump_accepted_messages {
+        tracing::debug!("{:?}", msg);
+    }
+    let asd = AisStationData::try_from(msg)?;
+    tracing::debug!("{:?}", asd);
+    args.metrics
+        .mmsi_last_seen
+        .with_label_values(&[&asd.mmsi.to_string()])
+        .set(asd.rxtime.timestamp() as f64);
+    let Some(decoder) = args.decoders.get(&ami) else {
+        tracing::warn!("No decoder for accepted message type {}, skipping it", ami);
+        return Ok(MessageOutcome::Skipped);
+    };
+    let columns = decoder.decode(msg)?;
+    tracing::debug!("{} {:?}", decoder.name(), columns);
+    if pmc.ignore_mmsi.contains(&asd.mmsi) {
+        tracing::debug!("Ignored message from {}", asd.mmsi);
+        return Ok(MessageOutcome::Ignored);
+    }
+    let observation = observation(args, decoder, &asd, &columns);
+    let columns = apply_not_available(columns, decoder.sentinels(), args.not_available);
+    let influx_line = influx_line(args, decoder.as_ref(), &asd, &columns, pmc);
+    let message_query = columns_as_query_arguments(&columns);
+    // Each sink can have its own field lists, so each gets a row of its own.
+    let sink_pmcs = args
+        .sinks
+        .iter()
+        .filter_map(|sink| sink.message_config_lookup.get(&ami));
+    let rows = std::iter::once(pmc)
+        .chain(sink_pmcs)
+        .map(|pmc| {
+            (
+                pmc.destination.url.clone(),
+                build_query_args(asd.clone(), message_query.clone(), args, pmc),
+            )
+        })
+        .collect();
+    Ok(MessageOutcome::Accepted {
+        rows,
+        influx_line,
+        observation,
+    })
+}
+
+/// The name the MMSI lookups give a station
+fn station_name<'a>(args: &'a ArgsState, station: &AisStationData) -> &'a str {
+    args.mmsi_lookup
+        .get(&station.mmsi.to_string())
+        .map_or("UNKNOWN", String::as_str)
+}
+
+/// The message as an entry in the history, if one is kept. Its not available codes are
+/// stored as NULL, whatever is sent to ERDDAP in their place. Message types whose decoder
+/// doesn't describe its fields have no table, and aren't kept.
+fn observation(
+    args: &ArgsState,
+    decoder: &Arc<dyn MessageDecoder>,
+    station: &AisStationData,
+    columns: &[Column],
+) -> Option<Observation> {
+    args.store.as_ref()?;
+    if args.dry_run || decoder.fields().is_empty() {
+        return None;
+    }
+    Some(Observation {
+        decoder: decoder.clone(),
+        station: station.clone(),
+        station_name: station_name(args, station).to_string(),
+        columns: apply_not_available(
+            columns.to_vec(),
+            decoder.sentinels(),
+            NotAvailableMode::Missing,
+        ),
+    })
+}
+
+/// The message as an InfluxDB point: a measurement named after its decoder, tagged with
+/// the station, with the fields published for its type under the names they're published
+/// by, timestamped with rxtime.
+fn influx_line(
+    args: &ArgsState,
+    decoder: &dyn MessageDecoder,
+    station: &AisStationData,
+    columns: &[Column],
+    pmc: &PerMessageConfig,
+) -> Option<String> {
+    args.influxdb.as_ref()?;
+    let fields: Vec<Column> = columns
+        .iter()
+        .filter(|c| pmc.publish_fields.is_empty() || pmc.publish_fields.contains(&c.name))
+        .map(|c| Column {
+            name: pmc.rename_fields.get(&c.name).unwrap_or(&c.name).clone(),
+            value: c.value.clone(),
+        })
+        .collect();
+    let mmsi = station.mmsi.to_string();
+    let details = args.stations.get(&mmsi);
+    let detail = |value: Option<String>| value.unwrap_or_default();
+    let latitude = detail(details.and_then(|d| d.latitude).map(|v| v.to_string()));
+    let longitude = detail(details.and_then(|d| d.longitude).map(|v| v.to_string()));
+    let owner = detail(details.and_then(|d| d.owner.clone()));
+    let tags = [
+        ("mmsi", mmsi.as_str()),
+        ("station_name", station_name(args, station)),
+        ("station_latitude", latitude.as_str()),
+        ("station_longitude", longitude.as_str()),
+        ("owner", owner.as_str()),
+    ];
+    line_protocol(decoder.name(), &tags, &fields, station.rxtime)
+}
+
+/// The rows and tallies from converting every message in a packet
+#[derive(Default)]
+struct PacketSummary {
+    /// Rows to send, with the URL of the dataset each is destined for
+    rows: Vec<(String, QueryRow)>,
+    /// Points to write to InfluxDB
+    influx_lines: Vec<String>,
+    /// Entries to add to the history
+    observations: Vec<Observation>,
+    total: u64,
+    /// Messages that were converted, or deliberately ignored
+    processed: u64,
+    skipped: u64,
+    ignored: u64,
+    /// The index in the packet of each message that could not be converted, and why
+    rejected: Vec<(usize, DecodeError)>,
+}
+
+impl PacketSummary {
+    fn describe(&self) -> String {
+        format!(
+            "Received {} messages, submitted {}, skipped {}, ignored {}, rejected {}",
+            self.total,
+            self.processed,
+            self.skipped,
+            self.ignored,
+            self.rejected.len()
+        )
     }
-    let mut processed_count = 0;
-    let mut skipped_count = 0;
-    let mut ignored_count = 0;
-    let mut total_count = 0;
-    for msg in payload.msgs {
-        total_count += 1;
-        let ami = AisMessageIdentifier::from(&msg);
-        // Is the message identifier allowed by the TOML setup?
-        if args.message_config_lookup.contains_key(&ami) {
-            tracing::info!("Message meets acceptance criteria {}, converting it", ami);
-            if args.dump_accepted_messages {
-                tracing::debug!("{:?}", msg);
+}
+
+/// Convert every message in a packet. A malformed message only costs that message, not the
+/// rest of the packet.
+fn convert_packet(args: &ArgsState, packet: &AisCatcherMessage) -> PacketSummary {
+    let mut summary = PacketSummary::default();
+    for (index, msg) in packet.msgs.iter().enumerate() {
+        summary.total += 1;
+        match convert_message(args, msg) {
+            Ok(MessageOutcome::Accepted {
+                rows,
+                influx_line,
+                observation,
+            }) => {
+                summary.rows.extend(rows);
+                summary.influx_lines.extend(influx_line);
+                summary.observations.extend(observation);
+                summary.processed += 1;
             }
-            let asd = AisStationData::from(&msg);
-            tracing::debug!("{:?}", asd);
-            let awd = AisType8Dac200Fid31::from(&msg);
-            tracing::debug!("{:?}", awd);
-            if args.message_config_lookup[&ami]
-                .ignore_mmsi
-                .contains(&asd.mmsi)
-            {
-                tracing::debug!("Ignored message from {}", asd.mmsi);
-                ignored_count += 1;
-            } else {
-                send_to_erddap(asd, awd, axum::extract::State(args.clone())).await;
+            Ok(MessageOutcome::Ignored) => {
+                summary.ignored += 1;
+                summary.processed += 1;
             }
-            processed_count += 1;
-        } else {
-            tracing::debug!("Ignoring message with identifier {}", ami);
-            skipped_count += 1;
+            Ok(MessageOutcome::Skipped) => summary.skipped += 1,
+            Err(error) => summary.rejected.push((index, error)),
+        }
+    }
+    summary
+}
+
+async fn process_aiscatcher_submission(
+    State(shared): State<SharedArgsState>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let args = shared.current();
+    // Capture the body before parsing it, so that packets the feeder can't understand are
+    // kept too.
+    if let Some(capture) = &args.capture {
+        if let Err(e) = capture.lock().unwrap().record(&body) {
+            tracing::error!("Could not capture packet: {}", e);
+        }
+    }
+    let payload: AisCatcherMessage = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            tracing::warn!("Could not parse packet: {}", e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"message": format!("Could not parse packet: {}", e)})),
+            );
         }
+    };
+    if args.dump_all_packets {
+        tracing::info!("{:?}", payload);
     }
-    let logmsg = format!(
-        "Received {} messages, submitted {}, skipped {}, ignored {}",
-        total_count, processed_count, skipped_count, ignored_count
+    let summary = convert_packet(&args, &payload);
+    let rejected: Vec<serde_json::Value> = summary
+        .rejected
+        .iter()
+        .map(|(index, error)| {
+            tracing::warn!("Rejected message {} of packet: {}", index, error);
+            json!({"index": index, "error": error.to_string()})
+        })
+        .collect();
+    args.metrics.packets_received.inc();
+    args.metrics.messages_received.inc_by(summary.total);
+    args.metrics.messages_submitted.inc_by(summary.processed);
+    args.metrics.messages_skipped.inc_by(summary.skipped);
+    args.metrics.messages_ignored.inc_by(summary.ignored);
+    args.metrics.messages_rejected.inc_by(rejected.len() as u64);
+    let logmsg = summary.describe();
+    let (dry_run_urls, _, _) = tokio::join!(
+        dispatch_rows(&args, summary.rows),
+        write_to_influxdb(&args, summary.influx_lines),
+        write_history(&args, summary.observations)
     );
     tracing::debug!("{}", logmsg);
-    (StatusCode::OK, Json(json!({"message": logmsg })))
+    let mut response = json!({"message": logmsg, "rejected": rejected });
+    if args.dry_run {
+        response["dry_run"] = json!(dry_run_urls);
+    }
+    (StatusCode::OK, Json(response))
 }
 
 fn build_and_filter_weather_data(
-    weather: AisType8Dac200Fid31,
-    args: &State<ArgsState>,
+    mut weather_query: QueryRow,
+    pmc: &PerMessageConfig,
 ) -> Vec<(String, String)> {
-    let mut weather_query = weather.as_query_arguments();
     // Apply the filters specified in the TOML config. If the vector is empty, nothing is removed,
     // to avoid having to list ALL the fields.
-    weather_query.retain(|(key, _)| args.publish_fields.iter().any(|s| s == key));
+    if !pmc.publish_fields.is_empty() {
+        weather_query.retain(|(key, _)| pmc.publish_fields.iter().any(|s| s == key));
+    }
     weather_query
 }
 
@@ -353,21 +1860,46 @@ fn rename_weather_keys(
     result
 }
 
+/// The fields the feeder sends for a message type, under the names ERDDAP sees them by, in
+/// the order build_query_args produces them. The author key isn't included.
+fn published_fields(
+    pmc: &PerMessageConfig,
+    decoder: &dyn MessageDecoder,
+) -> Vec<(String, FieldSpec)> {
+    let mut fields: Vec<(String, FieldSpec)> = STATION_FIELDS
+        .iter()
+        .map(|field| (field.name.to_string(), *field))
+        .collect();
+    for field in decoder.fields() {
+        if !pmc.publish_fields.is_empty() && !pmc.publish_fields.iter().any(|s| s == field.name) {
+            continue;
+        }
+        let name = pmc
+            .rename_fields
+            .get(field.name)
+            .cloned()
+            .unwrap_or_else(|| field.name.to_string());
+        fields.push((name, *field));
+    }
+    fields
+}
+
+/// Build the query arguments for a row. The author key isn't included; it's added when the
+/// row is submitted, so rows can be batched and spooled without it.
 fn build_query_args(
     station: AisStationData,
-    weather: AisType8Dac200Fid31,
-    args: &State<ArgsState>,
-) -> Vec<(String, String)> {
+    message_query: QueryRow,
+    args: &ArgsState,
+    pmc: &PerMessageConfig,
+) -> QueryRow {
     let station_query = station.as_query_arguments(&args.mmsi_lookup);
-    let weather_query = build_and_filter_weather_data(weather, &args);
-    let weather_query = rename_weather_keys(weather_query, &args.rename_fields);
-    let author = vec![("author".to_string(), args.author_key.to_string())];
+    let weather_query = build_and_filter_weather_data(message_query, pmc);
+    let weather_query = rename_weather_keys(weather_query, &pmc.rename_fields);
 
     // Build the arg string
     let mut query_args = vec![];
     query_args.extend(station_query);
     query_args.extend(weather_query);
-    query_args.extend(author);
     let result_vector: Vec<(String, String)> = query_args
         .into_iter()
         .map(|(first, second)| (first.to_string(), second))
@@ -376,51 +1908,761 @@ fn build_query_args(
     result_vector
 }
 
-async fn send_to_erddap(
-    station: AisStationData,
-    weather: AisType8Dac200Fid31,
-    args: State<ArgsState>,
-) {
-    let query_args = build_query_args(station, weather, &args);
+/// Why a batch of rows could not be delivered to ERDDAP
+#[derive(Debug)]
+enum SubmitError {
+    /// ERDDAP could not be reached, or had a problem of its own; the rows should be retried
+    Unavailable(String),
+    /// ERDDAP refused the rows; sending them again won't change that
+    Rejected(String),
+}
 
-    // Off to ERDDAP we go
-    let client = reqwest::Client::builder()
-        .danger_accept_invalid_certs(args.accept_invalid_certificates)
+fn build_client(accept_invalid_certificates: bool) -> reqwest::Client {
+    reqwest::Client::builder()
+        .danger_accept_invalid_certs(accept_invalid_certificates)
         .referer(false)
         .build()
-        .unwrap();
+        .unwrap()
+}
+
+/// Split rows into batches that can each go to ERDDAP as one request. ERDDAP needs every
+/// row in a multi-row insert to have the same columns, so each run of consecutive rows
+/// with the same column names is a batch, chunked to at most `max_rows`. Rows with other
+/// columns in between start a new batch rather than being moved, so the rows are still in
+/// the order they arrived.
+fn batch_rows(rows: Vec<QueryRow>, max_rows: usize) -> Vec<Vec<QueryRow>> {
+    let mut batches: Vec<Vec<QueryRow>> = vec![];
+    for row in rows {
+        match batches.last_mut() {
+            Some(batch)
+                if batch.len() < max_rows.max(1)
+                    && batch[0].len() == row.len()
+                    && batch[0]
+                        .iter()
+                        .zip(row.iter())
+                        .all(|((a, _), (b, _))| a == b) =>
+            {
+                batch.push(row)
+            }
+            _ => batches.push(vec![row]),
+        }
+    }
+    batches
+}
+
+/// Combine a batch of rows into the query arguments for a single .insert request. A
+/// single row is sent exactly as it is; several rows use ERDDAP's array syntax, one
+/// `[a,b,c]` value per column.
+fn merge_rows(batch: &[QueryRow]) -> QueryRow {
+    if batch.len() == 1 {
+        return batch[0].clone();
+    }
+    batch[0]
+        .iter()
+        .enumerate()
+        .map(|(column, (key, _))| {
+            let values: Vec<String> = batch
+                .iter()
+                .map(|row| quote_array_value(&row[column].1))
+                .collect();
+            (key.clone(), format!("[{}]", values.join(",")))
+        })
+        .collect()
+}
+
+/// Numbers go into an ERDDAP array as they are; anything else is sent as a JSON string,
+/// so that station names with commas or spaces can't break up the array.
+fn quote_array_value(value: &str) -> String {
+    if value.parse::<f64>().is_ok() {
+        value.to_string()
+    } else {
+        serde_json::Value::String(value.to_string()).to_string()
+    }
+}
+
+/// Serve the Prometheus metrics, bringing the retry spool figures up to date first.
+async fn serve_metrics(State(shared): State<SharedArgsState>) -> impl IntoResponse {
+    let args = shared.current();
+    let spools: Vec<_> = args
+        .spools()
+        .into_iter()
+        .map(|(sink, spool)| (sink, spool.lock()))
+        .collect();
+    args.metrics
+        .observe_spools(spools.iter().map(|(sink, spool)| (*sink, &**spool)));
+    drop(spools);
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        args.metrics.encode(),
+    )
+}
+
+/// The process is up and able to answer requests.
+async fn serve_healthz(State(shared): State<SharedArgsState>) -> impl IntoResponse {
+    let args = shared.current();
+    let started = args.health.lock().unwrap().started;
+    let uptime = chrono::Utc::now().signed_duration_since(started);
+    (
+        StatusCode::OK,
+        Json(json!({"status": "ok", "uptime_seconds": uptime.num_seconds()})),
+    )
+}
+
+/// The stored history of decoded messages, filtered by the mmsi, from and to parameters.
+async fn serve_observations(
+    State(shared): State<SharedArgsState>,
+    Query(query): Query<ObservationQuery>,
+) -> impl IntoResponse {
+    let args = shared.current();
+    let Some(store) = args.store.clone() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message": "No history is kept; set sqlite_path to keep one"})),
+        );
+    };
+    // A large query mustn't hold up the runtime's workers, and with them the ingest.
+    let observations = tokio::task::spawn_blocking(move || store.query(&query))
+        .await
+        .expect("history query panicked");
+    match observations {
+        Ok(observations) => (
+            StatusCode::OK,
+            Json(json!({ "observations": observations })),
+        ),
+        Err(e) => {
+            tracing::error!("Could not query the history: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message": format!("Could not query the history: {}", e)})),
+            )
+        }
+    }
+}
+
+/// The configuration is loaded and ERDDAP has been accepting data; 503 otherwise, with the
+/// details of the last success and failure either way.
+async fn serve_readyz(State(shared): State<SharedArgsState>) -> impl IntoResponse {
+    let args = shared.current();
+    let readiness = args
+        .health
+        .lock()
+        .unwrap()
+        .readiness(args.ready_window, chrono::Utc::now());
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+/// Send a batch of rows to an ERDDAP dataset's .insert endpoint, noting the outcome for
+/// /readyz and /metrics.
+async fn submit_rows(
+    args: &ArgsState,
+    client: &reqwest::Client,
+    destination: &ErddapDestination,
+    batch: &[QueryRow],
+) -> Result<ErddapResponse, SubmitError> {
+    let url = &destination.url;
+    let result = post_insert(client, &args.metrics, url, &destination.author_key, batch).await;
+    // ERDDAP may have taken some of the rows, so they aren't resent, but it's a failure.
+    let mismatched =
+        matches!(&result, Ok(response) if usize::from(response.nRowsReceived) != batch.len());
+    let outcome = match &result {
+        Ok(_) if mismatched => "mismatched",
+        Ok(_) => "sent",
+        Err(SubmitError::Unavailable(_)) => "failed",
+        Err(SubmitError::Rejected(_)) => "rejected",
+    };
+    args.metrics
+        .sink_rows
+        .with_label_values(&[&destination.sink, outcome])
+        .inc_by(batch.len() as u64);
+    let mut health = args.health.lock().unwrap();
+    match &result {
+        Ok(response) if mismatched => health.record_failure(
+            &destination.sink,
+            "insert",
+            url,
+            format!(
+                "{} but received {} of {} rows",
+                response.status,
+                response.nRowsReceived,
+                batch.len()
+            ),
+        ),
+        Ok(response) => health.record_success(
+            &destination.sink,
+            "insert",
+            url,
+            format!("{} for {} rows", response.status, response.nRowsReceived),
+        ),
+        Err(SubmitError::Unavailable(reason)) | Err(SubmitError::Rejected(reason)) => {
+            health.record_failure(&destination.sink, "insert", url, reason.clone())
+        }
+    }
+    result
+}
+
+/// Make the .insert request for a batch of rows, and interpret ERDDAP's answer.
+async fn post_insert(
+    client: &reqwest::Client,
+    metrics: &Metrics,
+    url: &str,
+    author_key: &str,
+    batch: &[QueryRow],
+) -> Result<ErddapResponse, SubmitError> {
+    let mut query_args = merge_rows(batch);
+    query_args.push(("author".to_string(), author_key.to_string()));
+    let timer = metrics.erddap_request_duration.start_timer();
     let response = client
-        .get(format!("{}.insert", args.url))
+        .get(format!("{}.insert", url))
         .query(&query_args)
         .send()
         .await;
+    timer.observe_duration();
+    let status_label = match &response {
+        Ok(result) => result.status().as_u16().to_string(),
+        Err(_) => "error".to_string(),
+    };
+    metrics
+        .erddap_responses
+        .with_label_values(&[&status_label])
+        .inc();
 
     // Errors can happen
-    if let Err(e) = response {
-        if e.is_request() {
-            tracing::error!("Request failed: {:?}", e);
+    let result = match response {
+        Ok(result) => result,
+        // reqwest's errors include the URL, and with it the author key.
+        Err(e) => {
+            return Err(SubmitError::Unavailable(format!(
+                "Request failed: {}",
+                e.without_url()
+            )))
         }
-    } else {
-        let result = response.unwrap();
-        match result.status() {
-            StatusCode::OK => {
-                tracing::info!(
-                    "ERDDAP said {}",
-                    result.json::<ErddapResponse>().await.unwrap().status
-                );
-            }
-            StatusCode::NOT_FOUND => {
+    };
+    match result.status() {
+        StatusCode::OK => {
+            let response = result
+                .json::<ErddapResponse>()
+                .await
+                .map_err(|e| SubmitError::Rejected(format!("Unreadable ERDDAP response: {}", e)))?;
+            if usize::from(response.nRowsReceived) != batch.len() {
                 tracing::error!(
-                    "URL not found. Please check hostname and path. It's also possible the requested URL \
-                    has fields that the ERDDAP server is not configured to accept ({}).",
-                    result.url()
+                    "Sent {} rows to ERDDAP, but it reports receiving {}",
+                    batch.len(),
+                    response.nRowsReceived
                 );
             }
-            _ => {
-                tracing::error!("{:?}", result);
+            Ok(response)
+        }
+        StatusCode::NOT_FOUND => Err(SubmitError::Rejected(format!(
+            "URL not found. Please check hostname and path. It's also possible the requested URL \
+            has fields that the ERDDAP server is not configured to accept ({}).",
+            redact_author(result.url().as_str())
+        ))),
+        // ERDDAP being restarted, overloaded, or behind a proxy that can't reach it.
+        status
+            if status.is_server_error()
+                || status == StatusCode::REQUEST_TIMEOUT
+                || status == StatusCode::TOO_MANY_REQUESTS =>
+        {
+            Err(SubmitError::Unavailable(format!(
+                "ERDDAP returned {}",
+                status
+            )))
+        }
+        status => Err(SubmitError::Rejected(format!(
+            "ERDDAP returned {} for {}",
+            status,
+            redact_author(result.url().as_str())
+        ))),
+    }
+}
+
+/// Replace the value of the author key in a URL, so it can be logged safely.
+fn redact_author(url: &str) -> String {
+    match url.find("author=") {
+        Some(start) => {
+            let value_start = start + "author=".len();
+            let value_end = url[value_start..]
+                .find('&')
+                .map(|i| value_start + i)
+                .unwrap_or(url.len());
+            format!("{}{}{}", &url[..value_start], REDACTED, &url[value_end..])
+        }
+        None => url.to_string(),
+    }
+}
+
+/// The .insert URL for a row, with the author key redacted, for showing rather than sending.
+fn redacted_insert_url(url: &str, row: &QueryRow) -> String {
+    let mut query_args = row.clone();
+    query_args.push(("author".to_string(), REDACTED.to_string()));
+    match reqwest::Url::parse_with_params(&format!("{}.insert", url), &query_args) {
+        Ok(insert_url) => insert_url.to_string(),
+        Err(error) => format!("{}.insert (not a valid URL: {})", url, error),
+    }
+}
+
+/// Put rows into the destination's sink's retry spool, one entry per row. The caller
+/// should hold the sink's sending lock, so the rows keep their place in line.
+async fn spool_rows(args: &ArgsState, destination: &ErddapDestination, rows: Vec<QueryRow>) {
+    let url = destination.url.clone();
+    let described = args
+        .spool_for(&destination.sink)
+        .with_spool(move |spool| {
+            for query_args in rows {
+                let entry = SpoolEntry {
+                    queued_at: chrono::Utc::now(),
+                    url: url.clone(),
+                    query_args,
+                };
+                if let Err(e) = spool.push(entry) {
+                    tracing::error!("Could not write row to the retry spool, it is lost: {}", e);
+                }
+            }
+            spool.describe()
+        })
+        .await;
+    tracing::warn!(
+        "Queued rows for retry to {}; {}",
+        destination.sink,
+        described
+    );
+}
+
+/// Remove rows that have been dealt with from the head of a spool, describing what's left.
+async fn pop_spooled(spool: &SharedSpool, count: usize) -> String {
+    spool
+        .with_spool(move |spool| {
+            if let Err(e) = spool.pop_front_n(count) {
+                tracing::error!("Could not update the retry spool: {}", e);
+            }
+            spool.describe()
+        })
+        .await
+}
+
+/// Hand rows over for sending. Without a batch window they're sent straight away, one
+/// request per batch; with a window, they wait in the pending list until the window
+/// closes or enough rows have built up to fill a batch. Returns the redacted URLs of any
+/// requests a dry run would have made.
+async fn dispatch_rows(args: &ArgsState, rows: Vec<(String, QueryRow)>) -> Vec<String> {
+    if rows.is_empty() {
+        return vec![];
+    }
+    let ready = if args.batch_window.is_zero() {
+        rows
+    } else {
+        let mut pending = args.pending_rows.lock().unwrap();
+        pending.extend(rows);
+        if pending.len() < args.batch_max_rows {
+            return vec![];
+        }
+        std::mem::take(&mut *pending)
+    };
+    flush_rows(args, ready).await
+}
+
+/// The rows waiting to go to one dataset
+type DatasetRows = (ErddapDestination, Vec<QueryRow>);
+
+/// Group rows by sink, and then by the dataset they're destined for.
+fn group_rows(args: &ArgsState, rows: Vec<(String, QueryRow)>) -> Vec<(String, Vec<DatasetRows>)> {
+    let mut by_sink: Vec<(String, Vec<DatasetRows>)> = vec![];
+    // Rows held for the batch window across a reload can be for a dataset that's gone.
+    let mut dropped: BTreeMap<String, usize> = BTreeMap::new();
+    for (url, row) in rows {
+        let Some(destination) = args.destination_for(&url) else {
+            *dropped.entry(url).or_default() += 1;
+            continue;
+        };
+        let index = match by_sink.iter().position(|(s, _)| *s == destination.sink) {
+            Some(index) => index,
+            None => {
+                by_sink.push((destination.sink.clone(), vec![]));
+                by_sink.len() - 1
+            }
+        };
+        let by_url = &mut by_sink[index].1;
+        match by_url.iter_mut().find(|(d, _)| d.url == url) {
+            Some((_, group)) => group.push(row),
+            None => by_url.push((destination, vec![row])),
+        }
+    }
+    for (url, count) in dropped {
+        tracing::warn!(
+            "Dropping {} rows for {}, which is no longer configured",
+            count,
+            url
+        );
+    }
+    by_sink
+}
+
+/// Send rows to their datasets, grouped by URL. The sinks are sent to at the same time, so
+/// that a slow or unreachable sink doesn't hold up the rest.
+async fn flush_rows(args: &ArgsState, rows: Vec<(String, QueryRow)>) -> Vec<String> {
+    let sends = group_rows(args, rows)
+        .into_iter()
+        .map(|(_, by_url)| async move {
+            let mut dry_run_urls = vec![];
+            for (destination, rows) in by_url {
+                write_outputs(args, &destination, &rows);
+                dry_run_urls.extend(send_to_erddap(args, &destination, rows).await);
+            }
+            dry_run_urls
+        });
+    join_all(sends).await.into_iter().flatten().collect()
+}
+
+/// Write the default sink's rows to the local outputs as well. They're written once, as
+/// they're sent, whether or not ERDDAP takes them, so rows that are spooled and retried
+/// aren't written twice.
+fn write_outputs(args: &ArgsState, destination: &ErddapDestination, rows: &[QueryRow]) {
+    if args.dry_run || destination.sink != DEFAULT_SINK {
+        return;
+    }
+    for output in &args.outputs {
+        let outcome = match output.write(destination, rows) {
+            Ok(()) => "written",
+            Err(error) => {
+                tracing::error!("Could not write {} rows: {}", output.name(), error);
+                "failed"
+            }
+        };
+        args.metrics
+            .output_rows
+            .with_label_values(&[output.name(), outcome])
+            .inc_by(rows.len() as u64);
+    }
+}
+
+/// Add entries to the history, if one is kept. The database is written on the blocking
+/// thread pool, so the runtime carries on meanwhile.
+async fn write_history(args: &ArgsState, observations: Vec<Observation>) {
+    let Some(store) = args.store.clone() else {
+        return;
+    };
+    if observations.is_empty() {
+        return;
+    }
+    let count = observations.len() as u64;
+    let result = tokio::task::spawn_blocking(move || store.insert(&observations))
+        .await
+        .expect("history write panicked");
+    let outcome = match result {
+        Ok(()) => "written",
+        Err(e) => {
+            tracing::error!("Could not add {} messages to the history: {}", count, e);
+            "failed"
+        }
+    };
+    args.metrics
+        .output_rows
+        .with_label_values(&["sqlite", outcome])
+        .inc_by(count);
+}
+
+/// Write points to the InfluxDB bucket, if there is one. Points InfluxDB can't take are
+/// logged and counted, but not retried.
+async fn write_to_influxdb(args: &ArgsState, lines: Vec<String>) {
+    let Some(influxdb) = &args.influxdb else {
+        return;
+    };
+    if lines.is_empty() {
+        return;
+    }
+    if args.dry_run {
+        tracing::info!("Dry run, not writing {} points to InfluxDB", lines.len());
+        return;
+    }
+    let response = build_client(influxdb.accept_invalid_certificates)
+        .post(format!(
+            "{}/api/v2/write",
+            influxdb.url.trim_end_matches('/')
+        ))
+        .query(&[
+            ("org", influxdb.org.as_str()),
+            ("bucket", influxdb.bucket.as_str()),
+            ("precision", "s"),
+        ])
+        .header(header::AUTHORIZATION, format!("Token {}", influxdb.token))
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(lines.join("\n"))
+        .send()
+        .await;
+    let outcome = match response {
+        Ok(result) if result.status().is_success() => "written",
+        Ok(result) => {
+            let status = result.status();
+            let detail = result.text().await.unwrap_or_default();
+            tracing::error!("InfluxDB returned {}: {}", status, detail.trim());
+            "failed"
+        }
+        Err(e) => {
+            tracing::error!("InfluxDB write failed: {}", e);
+            "failed"
+        }
+    };
+    args.metrics
+        .output_rows
+        .with_label_values(&["influxdb", outcome])
+        .inc_by(lines.len() as u64);
+}
+
+/// Put the rows still waiting for the batch window into their sinks' retry spools, for the
+/// next run to send. Sending them now could take longer than stopping is allowed to.
+async fn spool_pending_rows(args: &ArgsState) {
+    let rows = std::mem::take(&mut *args.pending_rows.lock().unwrap());
+    if rows.is_empty() {
+        return;
+    }
+    if args.dry_run {
+        tracing::info!("Dry run, dropping {} rows waiting to be sent", rows.len());
+        return;
+    }
+    for (_, by_url) in group_rows(args, rows) {
+        for (destination, rows) in by_url {
+            write_outputs(args, &destination, &rows);
+            args.metrics
+                .sink_rows
+                .with_label_values(&[&destination.sink, "queued"])
+                .inc_by(rows.len() as u64);
+            let spool = args.spool_for(&destination.sink);
+            let _sending = spool.sending().await;
+            spool_rows(args, &destination, rows).await;
+        }
+    }
+}
+
+/// Send the pending rows every time the batch window closes.
+async fn run_batch_flusher(shared: SharedArgsState, batch_window: Duration) {
+    let mut interval = tokio::time::interval(batch_window);
+    loop {
+        interval.tick().await;
+        let args = shared.current();
+        let rows = std::mem::take(&mut *args.pending_rows.lock().unwrap());
+        if !rows.is_empty() {
+            flush_rows(&args, rows).await;
+        }
+    }
+}
+
+async fn send_to_erddap(
+    args: &ArgsState,
+    destination: &ErddapDestination,
+    rows: Vec<QueryRow>,
+) -> Vec<String> {
+    let url = &destination.url;
+    let batches = batch_rows(rows, args.batch_max_rows);
+    if args.dry_run {
+        let urls: Vec<String> = batches
+            .iter()
+            .map(|batch| redacted_insert_url(url, &merge_rows(batch)))
+            .collect();
+        for (batch, insert_url) in batches.iter().zip(&urls) {
+            tracing::info!("Dry run, not sending {} rows: {}", batch.len(), insert_url);
+        }
+        return urls;
+    }
+    // Off to ERDDAP we go
+    let client = build_client(destination.accept_invalid_certificates);
+    let spool = args.spool_for(&destination.sink);
+    for batch in batches {
+        // Nothing else is sent to the sink until this batch has been sent or spooled.
+        let _sending = spool.sending().await;
+        // If older rows are still waiting for this sink, join the back of its queue so
+        // that rows arrive in order.
+        if !spool.lock().is_empty() {
+            args.metrics
+                .sink_rows
+                .with_label_values(&[&destination.sink, "queued"])
+                .inc_by(batch.len() as u64);
+            spool_rows(args, destination, batch).await;
+            continue;
+        }
+        match submit_rows(args, &client, destination, &batch).await {
+            Ok(response) => tracing::info!(
+                "{}: ERDDAP said {} for {} rows",
+                destination.sink,
+                response.status,
+                response.nRowsReceived
+            ),
+            Err(SubmitError::Unavailable(reason)) => {
+                tracing::error!("{}: {}", destination.sink, reason);
+                spool_rows(args, destination, batch).await;
+            }
+            Err(SubmitError::Rejected(reason)) => {
+                tracing::error!("{}: {}", destination.sink, reason)
             }
         }
     }
+    vec![]
+}
+
+/// Periodically fetch the .das page of every dataset rows can be sent to, so that /readyz
+/// notices ERDDAP going away (or coming back) between submissions.
+async fn run_erddap_probe(shared: SharedArgsState, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        // The datasets can change when the configuration is reloaded.
+        let args = shared.current();
+        let mut destinations = args.destinations();
+        destinations.sort_by(|a, b| a.url.cmp(&b.url));
+        destinations.dedup_by(|a, b| a.url == b.url);
+        for destination in destinations {
+            let (sink, url) = (destination.sink.as_str(), destination.url.as_str());
+            let client = build_client(destination.accept_invalid_certificates);
+            let response = client.get(format!("{}.das", url)).send().await;
+            let mut health = args.health.lock().unwrap();
+            match response {
+                Ok(result) if result.status().is_success() => {
+                    health.record_success(sink, "probe", url, result.status().to_string())
+                }
+                Ok(result) => {
+                    tracing::warn!("ERDDAP probe of {} returned {}", url, result.status());
+                    health.record_failure(sink, "probe", url, result.status().to_string())
+                }
+                Err(e) => {
+                    tracing::warn!("ERDDAP probe of {} failed: {}", url, e);
+                    health.record_failure(sink, "probe", url, format!("Request failed: {}", e))
+                }
+            }
+        }
+    }
+}
+
+/// When a sink's retry spool is next due an attempt, and how long to wait after that
+/// attempt if it fails too
+struct RetrySchedule {
+    due: tokio::time::Instant,
+    backoff: Duration,
+}
+
+/// Work through the retry spools, oldest rows first. Rows at the head of a spool are
+/// batched together when they share a URL and columns. While a sink is unavailable, the
+/// wait between its attempts doubles up to `max_backoff`; it resets as soon as rows get
+/// through. Each sink has its own spool and its own wait, so one being unavailable doesn't
+/// hold up the others.
+async fn run_retry_worker(
+    shared: SharedArgsState,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+) {
+    let mut schedules: HashMap<String, RetrySchedule> = HashMap::new();
+    loop {
+        let args = shared.current();
+        let mut progressed = false;
+        for (sink, spool) in args.spools() {
+            let now = tokio::time::Instant::now();
+            let schedule = schedules.entry(sink.to_string()).or_insert(RetrySchedule {
+                due: now,
+                backoff: initial_backoff,
+            });
+            if schedule.due > now {
+                continue;
+            }
+            match retry_head(&args, sink, spool, schedule.backoff).await {
+                Some(true) => {
+                    progressed = true;
+                    schedule.backoff = initial_backoff;
+                }
+                Some(false) => {
+                    schedule.due = now + schedule.backoff;
+                    schedule.backoff = (schedule.backoff * 2).min(max_backoff);
+                }
+                None => schedule.backoff = initial_backoff,
+            }
+        }
+        if !progressed {
+            let next_due = schedules
+                .values()
+                .map(|schedule| schedule.due)
+                .filter(|due| *due > tokio::time::Instant::now())
+                .min();
+            let idle = tokio::time::Instant::now() + initial_backoff;
+            tokio::time::sleep_until(next_due.map_or(idle, |due| due.min(idle))).await;
+        }
+    }
+}
+
+/// Retry the rows at the head of a sink's spool. Returns None when the spool is empty, and
+/// otherwise whether the sink answered; rows it rejected are discarded, and count as
+/// answered, as do rows for a dataset that's no longer configured, which are abandoned.
+/// `backoff` is only used to say when the next attempt will be.
+async fn retry_head(
+    args: &ArgsState,
+    sink: &str,
+    spool: &SharedSpool,
+    backoff: Duration,
+) -> Option<bool> {
+    let _sending = spool.sending().await;
+    let head = spool.lock().front_n(args.batch_max_rows);
+    let url = head.first().map(|entry| entry.url.clone())?;
+    let rows: Vec<QueryRow> = head
+        .into_iter()
+        .take_while(|entry| entry.url == url)
+        .map(|entry| entry.query_args)
+        .collect();
+    let Some(destination) = args.destination_for(&url) else {
+        let described = pop_spooled(spool, rows.len()).await;
+        tracing::error!(
+            "{}: abandoning {} spooled rows for {}, which is no longer configured; {}",
+            sink,
+            rows.len(),
+            url,
+            described
+        );
+        args.metrics
+            .sink_rows
+            .with_label_values(&[sink, "abandoned"])
+            .inc_by(rows.len() as u64);
+        return Some(true);
+    };
+    let batch = batch_rows(rows, args.batch_max_rows).swap_remove(0);
+    let client = build_client(destination.accept_invalid_certificates);
+    let result = submit_rows(args, &client, &destination, &batch).await;
+
+    let sink = &destination.sink;
+    Some(match result {
+        Ok(response) => {
+            let described = pop_spooled(spool, batch.len()).await;
+            tracing::info!(
+                "{}: retried {} rows accepted, ERDDAP said {}; {}",
+                sink,
+                batch.len(),
+                response.status,
+                described
+            );
+            true
+        }
+        Err(SubmitError::Rejected(reason)) => {
+            let described = pop_spooled(spool, batch.len()).await;
+            tracing::error!(
+                "{}: discarding {} spooled rows that ERDDAP rejected: {}; {}",
+                sink,
+                batch.len(),
+                reason,
+                described
+            );
+            true
+        }
+        Err(SubmitError::Unavailable(reason)) => {
+            tracing::warn!(
+                "{}: retry failed ({}); {}; next attempt in {}s",
+                sink,
+                reason,
+                spool.lock().describe(),
+                backoff.as_secs()
+            );
+            false
+        }
+    })
 }
 
 fn exec_user_manual() {
@@ -470,6 +2712,8 @@ fn exec_user_manual() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
+    use erddap_feeder::{DEFAULT_KEY, DEFAULT_MMSI, DEFAULT_URL};
 
     #[test]
     fn test_rename_weather_keys() {
@@ -482,4 +2726,609 @@ mod tests {
         let expected = vec![("renamed".to_string(), "value".to_string())];
         assert_eq!(x, expected);
     }
+
+    #[test]
+    fn test_message_config_destinations() {
+        let mut app_config = AppConfig::default();
+        app_config.message_config.push(AcceptedMessage {
+            r#type: 1,
+            dac: None,
+            fid: None,
+            ignore_mmsi: vec![],
+            erddap_url: Some("https://erddap.example.com/erddap/tabledap/tracks".to_string()),
+            erddap_key: Some("tracks_key".to_string()),
+            publish_fields: Some(vec!["lat".to_string(), "speed".to_string()]),
+            rename_fields: Some(vec![("speed".to_string(), "sog".to_string())]),
+        });
+        let lookup = build_message_config_lookup(&app_config);
+        let weather = AisMessageIdentifier {
+            r#type: 8,
+            dac: Some(200),
+            fid: Some(31),
+        };
+        let tracks = AisMessageIdentifier {
+            r#type: 1,
+            dac: None,
+            fid: None,
+        };
+        assert_eq!(lookup[&weather].destination.url, DEFAULT_URL);
+        assert_eq!(lookup[&weather].destination.author_key, DEFAULT_KEY);
+        assert_eq!(
+            lookup[&tracks].destination.url,
+            "https://erddap.example.com/erddap/tabledap/tracks"
+        );
+        assert_eq!(lookup[&tracks].destination.author_key, "tracks_key");
+
+        // Weather uses the global field lists, positions their own.
+        let query = vec![
+            ("lat".to_string(), "53.3".to_string()),
+            ("speed".to_string(), "10.5".to_string()),
+            ("wspeed".to_string(), "12".to_string()),
+        ];
+        let filtered = build_and_filter_weather_data(query.clone(), &lookup[&weather]);
+        let renamed = rename_weather_keys(filtered, &lookup[&weather].rename_fields);
+        assert_eq!(
+            renamed,
+            vec![
+                ("latitude".to_string(), "53.3".to_string()),
+                ("wspeed".to_string(), "12".to_string()),
+            ]
+        );
+        let filtered = build_and_filter_weather_data(query, &lookup[&tracks]);
+        let renamed = rename_weather_keys(filtered, &lookup[&tracks].rename_fields);
+        assert_eq!(
+            renamed,
+            vec![
+                ("lat".to_string(), "53.3".to_string()),
+                ("sog".to_string(), "10.5".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_publish_fields_publishes_everything() {
+        let app_config = AppConfig {
+            publish_fields: vec![],
+            ..Default::default()
+        };
+        let lookup = build_message_config_lookup(&app_config);
+        let pmc = lookup.values().next().unwrap();
+        let query = vec![("anything".to_string(), "1".to_string())];
+        assert_eq!(build_and_filter_weather_data(query.clone(), pmc), query);
+    }
+
+    fn row(mmsi: &str, name: &str) -> QueryRow {
+        vec![
+            ("mmsi".to_string(), mmsi.to_string()),
+            ("station_name".to_string(), name.to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_batch_rows_groups_by_columns() {
+        let rows = vec![row("1", "a"), row("2", "b"), row("4", "c")];
+        let batches = batch_rows(rows, 2);
+        assert_eq!(
+            batches,
+            vec![vec![row("1", "a"), row("2", "b")], vec![row("4", "c")]]
+        );
+    }
+
+    #[test]
+    fn test_batch_rows_keeps_interleaved_columns_in_order() {
+        let odd = |mmsi: &str| vec![("mmsi".to_string(), mmsi.to_string())];
+        let rows = vec![
+            row("1", "a"),
+            odd("2"),
+            odd("3"),
+            row("4", "b"),
+            row("5", "c"),
+            odd("6"),
+        ];
+        let batches = batch_rows(rows, 10);
+        assert_eq!(
+            batches,
+            vec![
+                vec![row("1", "a")],
+                vec![odd("2"), odd("3")],
+                vec![row("4", "b"), row("5", "c")],
+                vec![odd("6")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_rows() {
+        assert_eq!(merge_rows(&[row("1", "a")]), row("1", "a"));
+        let merged = merge_rows(&[row("1", "Dublin Bay"), row("2", "Kish, North")]);
+        assert_eq!(
+            merged,
+            vec![
+                ("mmsi".to_string(), "[1,2]".to_string()),
+                (
+                    "station_name".to_string(),
+                    r#"["Dublin Bay","Kish, North"]"#.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_packets_from_lines_or_single_packet() {
+        let packet = r#"{"protocol":"jsonaiscatcher","encodetime":"20231001120000","stationid":"test",
+            "receiver":{"description":"AIS-catcher","version":61,"engine":"x","setting":""},
+            "device":{"product":"rtl","vendor":"x","serial":"1","setting":""},
+            "msgs":[{"class":"AIS","type":8,"mmsi":992501234}]}"#;
+        let one_line = packet.replace('\n', "");
+        let dir = TempDir::new("replay");
+        std::fs::write(dir.join("a.json"), packet).unwrap();
+        std::fs::write(
+            dir.join("b.jsonl"),
+            format!("{}\nnot a packet\n\n{}\n", one_line, one_line),
+        )
+        .unwrap();
+
+        let files = replay_files(&[dir.path().to_path_buf()]).unwrap();
+        assert_eq!(files, vec![dir.join("a.json"), dir.join("b.jsonl")]);
+        assert_eq!(read_packets(&files[0]).unwrap().len(), 1);
+        let packets = read_packets(&files[1]).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[1].msgs.len(), 1);
+    }
+
+    #[test]
+    fn test_redacted_insert_url() {
+        let row = vec![
+            ("mmsi".to_string(), "992501234".to_string()),
+            ("station_name".to_string(), "Dublin Bay".to_string()),
+        ];
+        assert_eq!(
+            redacted_insert_url("https://erddap.example.com/erddap/tabledap/test", &row),
+            "https://erddap.example.com/erddap/tabledap/test.insert?mmsi=992501234&station_name=Dublin+Bay&author=REDACTED"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_returns_redacted_urls() {
+        let dir = TempDir::new("dry-run");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let args = ArgsState {
+            dry_run: true,
+            ..build_args_state(AppConfig::default(), spool)
+        };
+        let rows = vec![
+            (DEFAULT_URL.to_string(), row("992501234", "North")),
+            (DEFAULT_URL.to_string(), row("992505678", "South")),
+        ];
+        let urls = dispatch_rows(&args, rows).await;
+        assert_eq!(
+            urls,
+            vec![format!(
+                "{}.insert?mmsi=%5B992501234%2C992505678%5D&station_name=%5B%22North%22%2C%22South%22%5D&author=REDACTED",
+                DEFAULT_URL
+            )]
+        );
+        assert!(args.spool.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pending_rows_are_spooled_when_stopping() {
+        let dir = TempDir::new("pending");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let args = ArgsState {
+            batch_window: Duration::from_secs(60),
+            batch_max_rows: 10,
+            ..build_args_state(AppConfig::default(), spool)
+        };
+        let rows = vec![
+            (DEFAULT_URL.to_string(), row("992501234", "North")),
+            (DEFAULT_URL.to_string(), row("992505678", "South")),
+        ];
+        // Not enough to fill a batch, so they wait for the window
+        assert!(dispatch_rows(&args, rows).await.is_empty());
+        assert_eq!(args.pending_rows.lock().unwrap().len(), 2);
+
+        spool_pending_rows(&args).await;
+        assert!(args.pending_rows.lock().unwrap().is_empty());
+        let spooled = args.spool.lock().front_n(10);
+        assert_eq!(spooled.len(), 2);
+        assert_eq!(spooled[0].url, DEFAULT_URL);
+        assert_eq!(spooled[1].query_args, row("992505678", "South"));
+    }
+
+    #[tokio::test]
+    async fn test_sinks_get_their_own_rows_and_spool() {
+        let partner_url = "https://partner.example.com/erddap/tabledap/weather";
+        let dir = TempDir::new("sinks");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let mut app_config = AppConfig::default();
+        app_config.sinks.push(ErddapSink {
+            name: "partner".to_string(),
+            erddap_url: partner_url.to_string(),
+            erddap_key: "partner_key".to_string(),
+            erddap_key_file: None,
+            accept_invalid_certificates: true,
+            publish_fields: Some(vec!["wspeed".to_string()]),
+            rename_fields: None,
+        });
+        let args = ArgsState {
+            dry_run: true,
+            ..build_args_state(app_config.clone(), spool)
+        };
+        let weather = AisMessageIdentifier {
+            r#type: 8,
+            dac: Some(200),
+            fid: Some(31),
+        };
+        let partner = &args.sinks[0];
+        let pmc = &partner.message_config_lookup[&weather];
+        assert_eq!(pmc.publish_fields, vec!["wspeed".to_string()]);
+        assert_eq!(
+            pmc.rename_fields,
+            args.message_config_lookup[&weather].rename_fields
+        );
+        let destination = args.destination_for(partner_url).unwrap();
+        assert_eq!(destination.sink, "partner");
+        assert_eq!(destination.author_key, "partner_key");
+        assert!(destination.accept_invalid_certificates);
+        assert_eq!(
+            args.destination_for(DEFAULT_URL).unwrap().sink,
+            DEFAULT_SINK
+        );
+        assert_eq!(partner.spool.lock().path(), dir.join("spool.partner.jsonl"));
+
+        let rows = vec![
+            (DEFAULT_URL.to_string(), row("992501234", "North")),
+            (partner_url.to_string(), row("992501234", "North")),
+        ];
+        let urls = dispatch_rows(&args, rows).await;
+        assert_eq!(urls.len(), 2);
+        assert!(urls[1].starts_with(partner_url));
+
+        // Reloading keeps the sink's spool
+        let reloaded = reload_args_state(&args, app_config).unwrap();
+        assert!(partner.spool.ptr_eq(&reloaded.sinks[0].spool));
+    }
+
+    #[tokio::test]
+    async fn test_rows_stay_in_order_while_a_send_fails() {
+        // A stand-in for ERDDAP that holds on to the first insert until it's released, then
+        // fails it, and takes the rest. It says when the first has arrived.
+        #[derive(Clone, Default)]
+        struct Stand {
+            inserts: Arc<Mutex<Vec<String>>>,
+            received: Arc<tokio::sync::Notify>,
+            release: Arc<tokio::sync::Notify>,
+        }
+        let stand = Stand::default();
+        let app =
+            Router::new()
+                .route(
+                    "/erddap/tabledap/test.insert",
+                    get(
+                        |State(stand): State<Stand>,
+                         Query(query): Query<HashMap<String, String>>| async move {
+                            let first = {
+                                let mut inserts = stand.inserts.lock().unwrap();
+                                inserts.push(query["mmsi"].clone());
+                                inserts.len() == 1
+                            };
+                            if first {
+                                stand.received.notify_one();
+                                stand.release.notified().await;
+                                return Err(StatusCode::SERVICE_UNAVAILABLE);
+                            }
+                            Ok(Json(json!({"status": "success", "nRowsReceived": 1,
+                            "stringTimestamp": "2023-10-01T12:00:00Z",
+                            "numericTimestamp": 1.6961616E9})))
+                        },
+                    ),
+                )
+                .with_state(stand.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let dir = TempDir::new("order");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let app_config = AppConfig {
+            erddap_url: format!("http://{}/erddap/tabledap/test", address),
+            ..AppConfig::default()
+        };
+        let args = build_args_state(app_config, spool);
+        let destination = args.destination_for(&args.url).unwrap();
+        let mut second =
+            std::pin::pin!(send_to_erddap(&args, &destination, vec![row("2", "South")]));
+        let second = async {
+            // Start sending the second row while ERDDAP has the first, and only let the
+            // first fail once the second is waiting.
+            stand.received.notified().await;
+            assert!(futures::poll!(&mut second).is_pending());
+            stand.release.notify_one();
+            second.await
+        };
+        tokio::join!(
+            send_to_erddap(&args, &destination, vec![row("1", "North")]),
+            second
+        );
+
+        // The second row waited, and then queued up behind the first
+        assert_eq!(*stand.inserts.lock().unwrap(), vec!["1".to_string()]);
+        let spooled = args.spool.lock().front_n(10);
+        assert_eq!(spooled.len(), 2);
+        assert_eq!(spooled[0].query_args, row("1", "North"));
+        assert_eq!(spooled[1].query_args, row("2", "South"));
+    }
+
+    #[tokio::test]
+    async fn test_row_count_mismatch_is_a_failure() {
+        // A stand-in for ERDDAP that only ever owns up to receiving one row
+        let app = Router::new().route(
+            "/erddap/tabledap/test.insert",
+            get(|| async {
+                Json(json!({"status": "success", "nRowsReceived": 1,
+                    "stringTimestamp": "2023-10-01T12:00:00Z", "numericTimestamp": 1.6961616E9}))
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let dir = TempDir::new("mismatch");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let app_config = AppConfig {
+            erddap_url: format!("http://{}/erddap/tabledap/test", address),
+            ..AppConfig::default()
+        };
+        let args = build_args_state(app_config, spool);
+        let destination = args.destination_for(&args.url).unwrap();
+        let client = build_client(false);
+        let one = [row("992501234", "North")];
+        assert!(submit_rows(&args, &client, &destination, &one)
+            .await
+            .is_ok());
+        assert!(
+            args.health
+                .lock()
+                .unwrap()
+                .readiness(Duration::ZERO, chrono::Utc::now())
+                .ready
+        );
+
+        let two = [row("992501234", "North"), row("992505678", "South")];
+        assert!(submit_rows(&args, &client, &destination, &two)
+            .await
+            .is_ok());
+        let readiness = args
+            .health
+            .lock()
+            .unwrap()
+            .readiness(Duration::ZERO, chrono::Utc::now());
+        assert!(!readiness.ready);
+        assert!(readiness.reason.unwrap().contains("received 1 of 2 rows"));
+        let metrics = args.metrics.encode();
+        assert!(metrics.contains("sink_rows_total{outcome=\"mismatched\",sink=\"default\"} 2"));
+        assert!(args.spool.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rows_for_removed_datasets_are_not_sent() {
+        let dir = TempDir::new("removed");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let args = build_args_state(AppConfig::default(), spool);
+        let gone = "https://gone.example.com/erddap/tabledap/weather";
+        assert!(args.destination_for(gone).is_none());
+
+        // Waiting rows are dropped rather than sent with the default sink's key
+        let rows = vec![(gone.to_string(), row("992501234", "North"))];
+        assert!(group_rows(&args, rows).is_empty());
+
+        // Spooled rows are abandoned, and the rows behind them still get their turn
+        {
+            let mut spool = args.spool.lock();
+            for url in [gone, gone, DEFAULT_URL] {
+                spool
+                    .push(SpoolEntry {
+                        queued_at: chrono::Utc::now(),
+                        url: url.to_string(),
+                        query_args: row("992501234", "North"),
+                    })
+                    .unwrap();
+            }
+        }
+        assert_eq!(
+            retry_head(&args, DEFAULT_SINK, &args.spool, Duration::ZERO).await,
+            Some(true)
+        );
+        let remaining = args.spool.lock().front_n(10);
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].url, DEFAULT_URL);
+        assert!(args
+            .metrics
+            .encode()
+            .contains("sink_rows_total{outcome=\"abandoned\",sink=\"default\"} 2"));
+    }
+
+    #[tokio::test]
+    async fn test_influxdb_points_are_posted_to_the_write_endpoint() {
+        // A stand-in for InfluxDB that keeps the query, token and body of each write
+        type Writes = Arc<Mutex<Vec<(String, String, String)>>>;
+        let writes: Writes = Arc::default();
+        let app = Router::new()
+            .route(
+                "/api/v2/write",
+                post(
+                    |State(writes): State<Writes>,
+                     uri: axum::http::Uri,
+                     headers: axum::http::HeaderMap,
+                     body: String| async move {
+                        let token = headers[header::AUTHORIZATION].to_str().unwrap();
+                        let query = uri.query().unwrap_or_default().to_string();
+                        writes
+                            .lock()
+                            .unwrap()
+                            .push((query, token.to_string(), body));
+                        StatusCode::NO_CONTENT
+                    },
+                ),
+            )
+            .with_state(writes.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let dir = TempDir::new("influx");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let mut app_config = AppConfig::default();
+        app_config.mmsi_lookup[0].mmsi = "992501234".to_string();
+        app_config.mmsi_lookup[0].station_name = "Dublin Bay, North".to_string();
+        app_config.mmsi_lookup[0].owner = Some("Port of Dublin".to_string());
+        app_config.mmsi_lookup[0].latitude = Some(53.35);
+        app_config.influxdb = Some(InfluxConfig {
+            url: format!("http://{}/", address),
+            org: "harbour".to_string(),
+            bucket: "weather".to_string(),
+            token: "influx_secret".to_string(),
+            token_file: None,
+            accept_invalid_certificates: false,
+        });
+        let args = build_args_state(app_config, spool);
+        let packet: AisCatcherMessage = serde_json::from_str(
+            r#"{"protocol":"jsonaiscatcher","encodetime":"20231001120000","stationid":"test",
+            "receiver":{"description":"AIS-catcher","version":61,"engine":"x","setting":""},
+            "device":{"product":"rtl","vendor":"x","serial":"1","setting":""},
+            "msgs":[{"class":"AIS","rxtime":"20231001120000","type":8,"dac":200,"fid":31,
+                "mmsi":992501234,"signalpower":-20.5,"lat":53.3,"lon":-6.1,"wspeed":12,
+                "airtemp":12.5}]}"#,
+        )
+        .unwrap();
+        let summary = convert_packet(&args, &packet);
+        assert_eq!(summary.influx_lines.len(), 1);
+        write_to_influxdb(&args, summary.influx_lines).await;
+
+        let writes = writes.lock().unwrap();
+        assert_eq!(writes.len(), 1);
+        let (query, token, body) = &writes[0];
+        assert_eq!(query, "org=harbour&bucket=weather&precision=s");
+        assert_eq!(token, "Token influx_secret");
+        assert!(body.starts_with(
+            "imo289_met_hydro,mmsi=992501234,station_name=Dublin\\ Bay\\,\\ North,\
+             station_latitude=53.35,owner=Port\\ of\\ Dublin latitude=53.3,longitude=-6.1,"
+        ));
+        assert!(body.ends_with(",wspeed=12i 1696161600"));
+        // Only the published fields are written
+        assert!(!body.contains("airtemp"));
+        assert!(args.metrics.encode().contains(
+            "erddap_feeder_output_rows_total{outcome=\"written\",output=\"influxdb\"} 1"
+        ));
+    }
+
+    #[test]
+    fn test_reload_keeps_runtime_state() {
+        let dir = TempDir::new("reload");
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        let shared = SharedArgsState::new(ArgsState {
+            dry_run: true,
+            ..build_args_state(AppConfig::default(), spool)
+        });
+        let before = shared.current();
+
+        let mut app_config = AppConfig::default();
+        app_config.mmsi_lookup[0].station_name = "Renamed".to_string();
+        shared.replace(reload_args_state(&before, app_config).unwrap());
+
+        let after = shared.current();
+        assert_eq!(after.mmsi_lookup.get(DEFAULT_MMSI).unwrap(), "Renamed");
+        assert!(after.dry_run);
+        assert!(before.spool.ptr_eq(&after.spool));
+        assert!(Arc::ptr_eq(&before.health, &after.health));
+        // The old state stays usable by anything that was part way through with it
+        assert_ne!(before.mmsi_lookup.get(DEFAULT_MMSI).unwrap(), "Renamed");
+    }
+
+    #[test]
+    fn test_overrides_and_key_file() {
+        let cli = Cli::try_parse_from([
+            "erddap-feeder",
+            "run",
+            "--rename-fields",
+            "lat=latitude,lon=longitude",
+            "--mmsi-lookup",
+            "992501234=Dublin Bay,992505678=Howth",
+            "--not-available",
+            "omit",
+            "--message-config",
+            r#"[{"type": 1, "ignore_mmsi": []}]"#,
+        ])
+        .unwrap();
+        let Commands::Run(run) = cli.command else {
+            panic!("not the run subcommand");
+        };
+        let mut cfg = AppConfig::default();
+        run.overrides.apply(&mut cfg);
+        assert_eq!(
+            cfg.rename_fields[1],
+            ("lon".to_string(), "longitude".to_string())
+        );
+        assert_eq!(cfg.mmsi_lookup[0].station_name, "Dublin Bay");
+        assert_eq!(cfg.mmsi_lookup[1].mmsi, "992505678");
+        assert_eq!(cfg.not_available, NotAvailableMode::Omit);
+        assert_eq!(cfg.message_config[0].r#type, 1);
+        assert_eq!(cfg.erddap_url, DEFAULT_URL);
+
+        let dir = TempDir::new("key");
+        let key_file = dir.join("erddap_key");
+        std::fs::write(&key_file, "user_secret\n").unwrap();
+        assert_eq!(read_key_file(&key_file).unwrap(), "user_secret");
+
+        assert!(!format!("{:?}", cfg).contains(DEFAULT_KEY));
+    }
+
+    #[test]
+    fn test_config_path_replaces_config_file() {
+        let cli = Cli::try_parse_from([
+            "erddap-feeder",
+            "check",
+            "--config-path",
+            "/etc/feeder.yaml",
+        ])
+        .unwrap();
+        let Commands::Check(check) = cli.command else {
+            panic!("not the check subcommand");
+        };
+        assert_eq!(check.config.path(), PathBuf::from("/etc/feeder.yaml"));
+        assert!(Cli::try_parse_from([
+            "erddap-feeder",
+            "check",
+            "--config-file",
+            "other",
+            "--config-path",
+            "/etc/feeder.yaml"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_redact_author() {
+        assert_eq!(
+            redact_author("https://e.example.com/t.insert?mmsi=1&author=user_pass&wdir=3"),
+            "https://e.example.com/t.insert?mmsi=1&author=REDACTED&wdir=3"
+        );
+        assert_eq!(
+            redact_author("https://e.example.com/t.insert?author=user_pass"),
+            "https://e.example.com/t.insert?author=REDACTED"
+        );
+    }
 }
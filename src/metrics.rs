@@ -0,0 +1,221 @@
+use prometheus::{
+    Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Prometheus metrics for the feeder, served from the /metrics route.
+///
+/// Every metric is registered with a registry owned by this struct rather than the
+/// prometheus crate's global one, so that each instance (and each test) starts from zero.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Packets posted to /aiscatcher
+    pub packets_received: IntCounter,
+    /// Messages seen in those packets
+    pub messages_received: IntCounter,
+    /// Messages that were converted into rows, or deliberately ignored
+    pub messages_submitted: IntCounter,
+    /// Messages of a type the configuration does not accept
+    pub messages_skipped: IntCounter,
+    /// Messages from an MMSI on the ignore list for their type
+    pub messages_ignored: IntCounter,
+    /// Messages that could not be decoded
+    pub messages_rejected: IntCounter,
+    /// ERDDAP .insert responses, by HTTP status code ("error" when no response arrived)
+    pub erddap_responses: IntCounterVec,
+    /// Time taken by ERDDAP .insert requests
+    pub erddap_request_duration: Histogram,
+    /// Rows for each sink, by what became of them: sent, failed (and spooled), rejected,
+    /// mismatched (ERDDAP reported receiving a different number), abandoned (spooled for a
+    /// dataset that's no longer configured), or queued behind rows already waiting in the
+    /// sink's spool. Retried rows are counted again.
+    pub sink_rows: IntCounterVec,
+    /// Rows for each local output, by whether they were written or failed
+    pub output_rows: IntCounterVec,
+    /// When a message was last accepted from each MMSI, as a Unix timestamp
+    pub mmsi_last_seen: GaugeVec,
+    /// Rows waiting in the retry spools
+    pub spool_rows: IntGauge,
+    /// Rows waiting in each sink's retry spool
+    pub sink_spool_rows: IntGaugeVec,
+    /// How long the oldest row in the retry spool has been waiting
+    pub spool_oldest_age: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("erddap_feeder".to_string()), None).unwrap();
+        let metrics = Metrics {
+            packets_received: IntCounter::new(
+                "packets_received_total",
+                "Packets posted by AIS-catcher",
+            )
+            .unwrap(),
+            messages_received: IntCounter::new(
+                "messages_received_total",
+                "Messages received in AIS-catcher packets",
+            )
+            .unwrap(),
+            messages_submitted: IntCounter::new(
+                "messages_submitted_total",
+                "Messages accepted for ERDDAP, including those from ignored MMSIs",
+            )
+            .unwrap(),
+            messages_skipped: IntCounter::new(
+                "messages_skipped_total",
+                "Messages of a type that is not configured",
+            )
+            .unwrap(),
+            messages_ignored: IntCounter::new(
+                "messages_ignored_total",
+                "Messages from MMSIs on an ignore list",
+            )
+            .unwrap(),
+            messages_rejected: IntCounter::new(
+                "messages_rejected_total",
+                "Messages that could not be decoded",
+            )
+            .unwrap(),
+            erddap_responses: IntCounterVec::new(
+                Opts::new(
+                    "erddap_responses_total",
+                    "ERDDAP .insert responses by HTTP status",
+                ),
+                &["status"],
+            )
+            .unwrap(),
+            erddap_request_duration: Histogram::with_opts(HistogramOpts::new(
+                "erddap_request_duration_seconds",
+                "Time taken by ERDDAP .insert requests",
+            ))
+            .unwrap(),
+            sink_rows: IntCounterVec::new(
+                Opts::new("sink_rows_total", "Rows for each sink, by outcome"),
+                &["sink", "outcome"],
+            )
+            .unwrap(),
+            output_rows: IntCounterVec::new(
+                Opts::new(
+                    "output_rows_total",
+                    "Rows for each local output, by outcome",
+                ),
+                &["output", "outcome"],
+            )
+            .unwrap(),
+            mmsi_last_seen: GaugeVec::new(
+                Opts::new(
+                    "mmsi_last_seen_timestamp_seconds",
+                    "When a message was last accepted from each MMSI",
+                ),
+                &["mmsi"],
+            )
+            .unwrap(),
+            spool_rows: IntGauge::new("spool_rows", "Rows waiting in the retry spools").unwrap(),
+            sink_spool_rows: IntGaugeVec::new(
+                Opts::new("sink_spool_rows", "Rows waiting in each sink's retry spool"),
+                &["sink"],
+            )
+            .unwrap(),
+            spool_oldest_age: Gauge::new(
+                "spool_oldest_age_seconds",
+                "How long the oldest row in the retry spools has been waiting",
+            )
+            .unwrap(),
+            registry,
+        };
+        metrics.register_all();
+        metrics
+    }
+
+    fn register_all(&self) {
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(self.packets_received.clone()),
+            Box::new(self.messages_received.clone()),
+            Box::new(self.messages_submitted.clone()),
+            Box::new(self.messages_skipped.clone()),
+            Box::new(self.messages_ignored.clone()),
+            Box::new(self.messages_rejected.clone()),
+            Box::new(self.erddap_responses.clone()),
+            Box::new(self.erddap_request_duration.clone()),
+            Box::new(self.sink_rows.clone()),
+            Box::new(self.output_rows.clone()),
+            Box::new(self.mmsi_last_seen.clone()),
+            Box::new(self.spool_rows.clone()),
+            Box::new(self.sink_spool_rows.clone()),
+            Box::new(self.spool_oldest_age.clone()),
+        ];
+        for collector in collectors {
+            self.registry.register(collector).unwrap();
+        }
+    }
+
+    /// Note the queue depth of each sink's retry spool, so it can be scraped.
+    pub fn observe_spools<'a>(
+        &self,
+        spools: impl IntoIterator<Item = (&'a str, &'a crate::spool::Spool)>,
+    ) {
+        let mut rows = 0;
+        let mut oldest = None;
+        self.sink_spool_rows.reset();
+        for (sink, spool) in spools {
+            rows += spool.len();
+            self.sink_spool_rows
+                .with_label_values(&[sink])
+                .set(spool.len() as i64);
+            oldest = oldest.max(spool.oldest_age());
+        }
+        self.spool_rows.set(rows as i64);
+        let age = oldest
+            .map(|age| age.num_milliseconds() as f64 / 1000.0)
+            .unwrap_or(0.0);
+        self.spool_oldest_age.set(age);
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_includes_counters_and_labels() {
+        let metrics = Metrics::new();
+        metrics.messages_received.inc_by(3);
+        metrics.erddap_responses.with_label_values(&["200"]).inc();
+        metrics
+            .sink_rows
+            .with_label_values(&["partner", "sent"])
+            .inc_by(2);
+        metrics
+            .output_rows
+            .with_label_values(&["jsonl", "written"])
+            .inc();
+        metrics
+            .mmsi_last_seen
+            .with_label_values(&["992501301"])
+            .set(1_700_000_000.0);
+        let text = metrics.encode();
+        assert!(text.contains("erddap_feeder_messages_received_total 3"));
+        assert!(text.contains("erddap_feeder_erddap_responses_total{status=\"200\"} 1"));
+        assert!(text.contains("erddap_feeder_mmsi_last_seen_timestamp_seconds{mmsi=\"992501301\"}"));
+        assert!(text.contains("erddap_feeder_sink_rows_total{outcome=\"sent\",sink=\"partner\"} 2"));
+        assert!(text
+            .contains("erddap_feeder_output_rows_total{outcome=\"written\",output=\"jsonl\"} 1"));
+        assert!(text.contains("erddap_feeder_spool_rows 0"));
+    }
+}
@@ -0,0 +1,526 @@
+use crate::schema::dataset_id;
+use crate::{ErddapDestination, QueryRow};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Somewhere on local disk that rows are written to as well as being sent to ERDDAP.
+pub trait RowOutput: Send + Sync {
+    /// The name the output is known by in logs
+    fn name(&self) -> &str;
+
+    /// Write rows bound for an ERDDAP dataset. The rows are as build_query_args produces
+    /// them, without the author key.
+    fn write(&self, destination: &ErddapDestination, rows: &[QueryRow]) -> io::Result<()>;
+}
+
+/// The columns ERDDAP adds to every row it's given: when, who by, and whether it was an
+/// insert (0) or a delete (1).
+const ERDDAP_COLUMNS: [&str; 3] = ["timestamp", "author", "command"];
+
+/// Files that start with a line of column names, and the columns of each one written to so
+/// far. A file's columns can't change, so rows with other columns (after a change of
+/// publish_fields, say) go to a file of their own: `<name>.<ext>`, then `<name>-1.<ext>`
+/// and so on.
+struct HeaderedFiles {
+    format: Format,
+    headers: Mutex<HashMap<PathBuf, Vec<String>>>,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+        }
+    }
+
+    fn encode_header(self, columns: &[String]) -> io::Result<String> {
+        match self {
+            Format::Jsonl => Ok(serde_json::to_string(columns)?),
+            Format::Csv => csv_line(columns),
+        }
+    }
+
+    /// The column names on the first line of an existing file, if there is one.
+    fn read_header(self, path: &Path) -> io::Result<Option<Vec<String>>> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut line = String::new();
+        BufReader::new(file).read_line(&mut line)?;
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        match self {
+            Format::Jsonl => Ok(Some(serde_json::from_str(&line)?)),
+            Format::Csv => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(line.as_bytes());
+                let record = reader.records().next().transpose()?.unwrap_or_default();
+                Ok(Some(record.iter().map(|c| c.to_string()).collect()))
+            }
+        }
+    }
+}
+
+impl HeaderedFiles {
+    fn new(format: Format) -> HeaderedFiles {
+        HeaderedFiles {
+            format,
+            headers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Append a line to the first file named `name` in `dir` that has, or can be given, the
+    /// columns.
+    fn append(&self, dir: &Path, name: &str, columns: &[String], line: &str) -> io::Result<()> {
+        let extension = self.format.extension();
+        let mut headers = self.headers.lock().unwrap();
+        let mut suffix = 0;
+        loop {
+            let path = match suffix {
+                0 => dir.join(format!("{}.{}", name, extension)),
+                n => dir.join(format!("{}-{}.{}", name, n, extension)),
+            };
+            if !headers.contains_key(&path) {
+                if let Some(header) = self.format.read_header(&path)? {
+                    headers.insert(path.clone(), header);
+                }
+            }
+            match headers.get(&path) {
+                Some(header) if header != columns => {
+                    suffix += 1;
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    std::fs::create_dir_all(dir)?;
+                    append_line(&path, &self.format.encode_header(columns)?)?;
+                    headers.insert(path.clone(), columns.to_vec());
+                }
+            }
+            return append_line(&path, line);
+        }
+    }
+}
+
+/// The value of a column in a row
+fn column<'a>(row: &'a QueryRow, name: &str) -> Option<&'a str> {
+    row.iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// The year, and the time up to the given length, from a row's time column, which is
+/// yyyy-MM-ddTHH:mm:ssZ.
+fn row_period(row: &QueryRow, length: usize) -> Option<(u32, &str)> {
+    let time = column(row, "time")?;
+    let year = time.get(..4)?.parse().ok()?;
+    Some((year, time.get(..length)?))
+}
+
+/// Writes rows to JSONL files that ERDDAP can read as they are, laid out like the
+/// `mmsi/10years` httpGetDirectoryStructure with a file per day:
+/// `<dir>/<dataset ID>/<mmsi>/<decade>/<yyyy-mm-dd>.jsonl`.
+///
+/// Like ERDDAP's own files, each starts with a line of column names, and every row has the
+/// timestamp, author and command columns ERDDAP adds.
+pub struct JsonlOutput {
+    dir: PathBuf,
+    files: HeaderedFiles,
+}
+
+impl JsonlOutput {
+    pub fn new(dir: &Path) -> JsonlOutput {
+        JsonlOutput {
+            dir: dir.to_path_buf(),
+            files: HeaderedFiles::new(Format::Jsonl),
+        }
+    }
+}
+
+impl RowOutput for JsonlOutput {
+    fn name(&self) -> &str {
+        "jsonl"
+    }
+
+    fn write(&self, destination: &ErddapDestination, rows: &[QueryRow]) -> io::Result<()> {
+        let dataset = dataset_id(&destination.url).unwrap_or("unknown");
+        // ERDDAP records the author's name, the part of the key before the password.
+        let author = destination
+            .author_key
+            .split_once('_')
+            .map_or("", |(author, _)| author);
+        let timestamp = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+        for row in rows {
+            let mut columns: Vec<String> = row.iter().map(|(key, _)| key.clone()).collect();
+            columns.extend(ERDDAP_COLUMNS.iter().map(|c| c.to_string()));
+            let mut values: Vec<serde_json::Value> =
+                row.iter().map(|(_, value)| json_value(value)).collect();
+            values.extend([timestamp.into(), author.into(), 0.into()]);
+            let (decade, day) = match row_period(row, 10) {
+                Some((year, day)) => ((year - year % 10).to_string(), day),
+                None => ("unknown".to_string(), "unknown"),
+            };
+            let dir = self
+                .dir
+                .join(dataset)
+                .join(column(row, "mmsi").unwrap_or("unknown"))
+                .join(decade);
+            self.files
+                .append(&dir, day, &columns, &serde_json::to_string(&values)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Numbers are written as numbers, and everything else as strings. NaN, which JSON can't
+/// hold, is written as null, which ERDDAP reads as missing.
+fn json_value(value: &str) -> serde_json::Value {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_nan() => serde_json::Value::Null,
+        Ok(_) => serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string())),
+        Err(_) => serde_json::Value::String(value.to_string()),
+    }
+}
+
+/// How much time each CSV file covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Monthly,
+}
+
+impl CsvRotation {
+    /// How much of the row's time names its file: yyyy-MM-ddTHH, yyyy-MM-dd or yyyy-MM
+    fn length(self) -> usize {
+        match self {
+            CsvRotation::Hourly => 13,
+            CsvRotation::Daily => 10,
+            CsvRotation::Monthly => 7,
+        }
+    }
+}
+
+/// Writes rows to plain CSV files, one per station for each hour, day or month by the rows'
+/// time: `<dir>/<dataset ID>/<mmsi>/<yyyy-mm-dd>.csv`.
+///
+/// The columns are those the configuration publishes to the row's dataset, in a fixed
+/// order, so every file for a dataset has the same header whichever fields a row happens to
+/// have; fields a row doesn't have (such as not available values left out) are left empty.
+pub struct CsvOutput {
+    dir: PathBuf,
+    rotation: CsvRotation,
+    /// The columns published to each dataset URL. Rows for a dataset that isn't here, or
+    /// with columns it doesn't list, have their own columns added at the end.
+    columns: HashMap<String, Vec<String>>,
+    files: HeaderedFiles,
+}
+
+impl CsvOutput {
+    pub fn new(
+        dir: &Path,
+        rotation: CsvRotation,
+        columns: HashMap<String, Vec<String>>,
+    ) -> CsvOutput {
+        CsvOutput {
+            dir: dir.to_path_buf(),
+            rotation,
+            columns,
+            files: HeaderedFiles::new(Format::Csv),
+        }
+    }
+}
+
+impl RowOutput for CsvOutput {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn write(&self, destination: &ErddapDestination, rows: &[QueryRow]) -> io::Result<()> {
+        let dataset = dataset_id(&destination.url).unwrap_or("unknown");
+        let published = self.columns.get(&destination.url);
+        for row in rows {
+            let mut columns = published.cloned().unwrap_or_default();
+            for (key, _) in row {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+            let values: Vec<&str> = columns
+                .iter()
+                .map(|name| column(row, name).unwrap_or(""))
+                .collect();
+            let period = row_period(row, self.rotation.length()).map_or("unknown", |(_, p)| p);
+            let dir = self
+                .dir
+                .join(dataset)
+                .join(column(row, "mmsi").unwrap_or("unknown"));
+            self.files
+                .append(&dir, period, &columns, &csv_line(&values)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// A CSV record, quoted where it needs to be, without its line ending
+fn csv_line<T: AsRef<[u8]>>(fields: &[T]) -> io::Result<String> {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    writer.write_record(fields)?;
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    let line = String::from_utf8_lossy(&bytes);
+    Ok(line.trim_end_matches('\n').to_string())
+}
+
+fn append_line(path: &Path, line: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{}\n", line).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const URL: &str = "https://erddap.example.com/erddap/tabledap/ais_weather";
+
+    fn destination() -> ErddapDestination {
+        ErddapDestination {
+            sink: "default".to_string(),
+            url: URL.to_string(),
+            author_key: "feeder_secret".to_string(),
+            accept_invalid_certificates: false,
+        }
+    }
+
+    fn jsonl_row(time: &str, wspeed: &str) -> QueryRow {
+        vec![
+            ("time".to_string(), time.to_string()),
+            ("station_name".to_string(), "Dublin Bay, North".to_string()),
+            ("mmsi".to_string(), "992501234".to_string()),
+            ("wspeed".to_string(), wspeed.to_string()),
+        ]
+    }
+
+    /// The lines of one of the test station's JSONL files from the 2020s
+    fn jsonl_lines(dir: &TempDir, name: &str) -> Vec<String> {
+        std::fs::read_to_string(dir.join("ais_weather/992501234/2020").join(name))
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_jsonl_output_lays_out_files_like_erddap() {
+        let dir = TempDir::new("jsonl-layout");
+        JsonlOutput::new(dir.path())
+            .write(&destination(), &[jsonl_row("2023-10-01T12:00:00Z", "12")])
+            .unwrap();
+        let lines = jsonl_lines(&dir, "2023-10-01.jsonl");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            r#"["time","station_name","mmsi","wspeed","timestamp","author","command"]"#
+        );
+        assert!(
+            lines[1].starts_with(r#"["2023-10-01T12:00:00Z","Dublin Bay, North",992501234,12,"#)
+        );
+    }
+
+    #[test]
+    fn test_jsonl_output_records_the_author_but_not_the_key() {
+        let dir = TempDir::new("jsonl-author");
+        JsonlOutput::new(dir.path())
+            .write(&destination(), &[jsonl_row("2023-10-01T12:00:00Z", "12")])
+            .unwrap();
+        let lines = jsonl_lines(&dir, "2023-10-01.jsonl");
+        assert!(lines[1].ends_with(r#","feeder",0]"#));
+        assert!(!lines.concat().contains("secret"));
+    }
+
+    #[test]
+    fn test_jsonl_output_writes_nan_as_null() {
+        let dir = TempDir::new("jsonl-nan");
+        JsonlOutput::new(dir.path())
+            .write(&destination(), &[jsonl_row("2023-10-01T13:00:00Z", "NaN")])
+            .unwrap();
+        let lines = jsonl_lines(&dir, "2023-10-01.jsonl");
+        assert!(lines[1].contains(r#",992501234,null,"#));
+    }
+
+    #[test]
+    fn test_jsonl_output_starts_a_new_file_for_other_columns() {
+        let dir = TempDir::new("jsonl-columns");
+        JsonlOutput::new(dir.path())
+            .write(&destination(), &[jsonl_row("2023-10-01T12:00:00Z", "12")])
+            .unwrap();
+        // A second output, as after a restart, picks up the existing file's columns.
+        let mut narrower = jsonl_row("2023-10-01T14:00:00Z", "9");
+        narrower.pop();
+        JsonlOutput::new(dir.path())
+            .write(
+                &destination(),
+                &[jsonl_row("2023-10-01T14:00:00Z", "9"), narrower],
+            )
+            .unwrap();
+        assert_eq!(jsonl_lines(&dir, "2023-10-01.jsonl").len(), 3);
+        let other = jsonl_lines(&dir, "2023-10-01-1.jsonl");
+        assert_eq!(other.len(), 2);
+        assert!(other[0].starts_with(r#"["time","station_name","mmsi","timestamp""#));
+    }
+
+    fn csv_row(time: &str, fields: &[(&str, &str)]) -> QueryRow {
+        let mut row = vec![
+            ("time".to_string(), time.to_string()),
+            ("station_name".to_string(), "Dublin Bay, North".to_string()),
+            ("mmsi".to_string(), "992501234".to_string()),
+        ];
+        row.extend(fields.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        row
+    }
+
+    fn published(names: &[&str]) -> HashMap<String, Vec<String>> {
+        HashMap::from([(
+            URL.to_string(),
+            names.iter().map(|name| name.to_string()).collect(),
+        )])
+    }
+
+    fn csv_file(dir: &TempDir, name: &str) -> String {
+        std::fs::read_to_string(dir.join("ais_weather/992501234").join(name)).unwrap()
+    }
+
+    const COLUMNS: [&str; 5] = ["time", "station_name", "mmsi", "wspeed", "wdir"];
+
+    #[test]
+    fn test_csv_output_keeps_the_published_columns() {
+        let dir = TempDir::new("csv-columns");
+        CsvOutput::new(dir.path(), CsvRotation::Daily, published(&COLUMNS))
+            .write(
+                &destination(),
+                &[
+                    // Out of order, and with the station name needing quotes
+                    csv_row("2023-10-01T12:00:00Z", &[("wdir", "270"), ("wspeed", "12")]),
+                    // A field left out as not available keeps its place
+                    csv_row("2023-10-01T13:00:00Z", &[("wdir", "280")]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            csv_file(&dir, "2023-10-01.csv"),
+            "time,station_name,mmsi,wspeed,wdir\n\
+             2023-10-01T12:00:00Z,\"Dublin Bay, North\",992501234,12,270\n\
+             2023-10-01T13:00:00Z,\"Dublin Bay, North\",992501234,,280\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_output_adds_unpublished_columns_at_the_end() {
+        let dir = TempDir::new("csv-extra");
+        CsvOutput::new(dir.path(), CsvRotation::Daily, published(&COLUMNS))
+            .write(
+                &destination(),
+                &[csv_row("2023-10-01T12:00:00Z", &[("airtemp", "11.5")])],
+            )
+            .unwrap();
+        assert_eq!(
+            csv_file(&dir, "2023-10-01.csv"),
+            "time,station_name,mmsi,wspeed,wdir,airtemp\n\
+             2023-10-01T12:00:00Z,\"Dublin Bay, North\",992501234,,,11.5\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_output_starts_a_new_file_when_the_header_changes() {
+        let dir = TempDir::new("csv-header");
+        CsvOutput::new(dir.path(), CsvRotation::Daily, published(&COLUMNS))
+            .write(
+                &destination(),
+                &[csv_row("2023-10-01T12:00:00Z", &[("wspeed", "12")])],
+            )
+            .unwrap();
+        // After a restart with a field dropped from publish_fields
+        CsvOutput::new(dir.path(), CsvRotation::Daily, published(&COLUMNS[..4]))
+            .write(
+                &destination(),
+                &[csv_row("2023-10-01T14:00:00Z", &[("wspeed", "8")])],
+            )
+            .unwrap();
+        assert_eq!(csv_file(&dir, "2023-10-01.csv").lines().count(), 2);
+        assert_eq!(
+            csv_file(&dir, "2023-10-01-1.csv"),
+            "time,station_name,mmsi,wspeed\n\
+             2023-10-01T14:00:00Z,\"Dublin Bay, North\",992501234,8\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_output_rotates_daily() {
+        let dir = TempDir::new("csv-daily");
+        CsvOutput::new(dir.path(), CsvRotation::Daily, published(&COLUMNS))
+            .write(
+                &destination(),
+                &[
+                    csv_row("2023-10-01T23:59:59Z", &[]),
+                    csv_row("2023-10-02T00:00:00Z", &[]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(csv_file(&dir, "2023-10-01.csv").lines().count(), 2);
+        assert_eq!(csv_file(&dir, "2023-10-02.csv").lines().count(), 2);
+    }
+
+    #[test]
+    fn test_csv_output_rotates_hourly() {
+        let dir = TempDir::new("csv-hourly");
+        CsvOutput::new(dir.path(), CsvRotation::Hourly, HashMap::new())
+            .write(
+                &destination(),
+                &[
+                    csv_row("2023-10-01T15:30:00Z", &[]),
+                    csv_row("2023-10-01T15:59:59Z", &[]),
+                    csv_row("2023-10-01T16:00:00Z", &[]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(csv_file(&dir, "2023-10-01T15.csv").lines().count(), 3);
+        assert_eq!(csv_file(&dir, "2023-10-01T16.csv").lines().count(), 2);
+    }
+
+    #[test]
+    fn test_csv_output_rotates_monthly() {
+        let dir = TempDir::new("csv-monthly");
+        CsvOutput::new(dir.path(), CsvRotation::Monthly, HashMap::new())
+            .write(
+                &destination(),
+                &[
+                    csv_row("2023-10-01T00:00:00Z", &[]),
+                    csv_row("2023-10-31T23:59:59Z", &[]),
+                    csv_row("2023-11-01T00:00:00Z", &[]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(csv_file(&dir, "2023-10.csv").lines().count(), 3);
+        assert_eq!(csv_file(&dir, "2023-11.csv").lines().count(), 2);
+    }
+}
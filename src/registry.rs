@@ -0,0 +1,109 @@
+use crate::MMSILookup;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Read a registry of MMSIs and station names. CSV files need a header row naming the
+/// columns: `mmsi` and `station_name`, and optionally `latitude`, `longitude` and `owner`;
+/// other columns are ignored. JSON files hold an array of objects with the same keys.
+pub fn load_mmsi_registry(path: &Path) -> Result<Vec<MMSILookup>, String> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("csv") => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_path(path)
+                .map_err(|e| e.to_string())?;
+            reader
+                .deserialize()
+                .collect::<Result<Vec<MMSILookup>, csv::Error>>()
+                .map_err(|e| e.to_string())
+        }
+        Some("json") => {
+            let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        }
+        _ => Err(format!(
+            "{} should end in .csv or .json to say what format it's in",
+            path.display()
+        )),
+    }
+}
+
+/// Combine the mmsi_lookup entries from the configuration file with those from a registry.
+/// An MMSI in both is given the configuration file's entry, so a station can be renamed
+/// locally without editing the registry.
+pub fn merge_mmsi_lookups(inline: &[MMSILookup], registry: Vec<MMSILookup>) -> Vec<MMSILookup> {
+    let inline_mmsis: HashSet<&str> = inline.iter().map(|entry| entry.mmsi.as_str()).collect();
+    let mut merged = inline.to_vec();
+    merged.extend(
+        registry
+            .into_iter()
+            .filter(|entry| !inline_mmsis.contains(entry.mmsi.as_str())),
+    );
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::path::PathBuf;
+
+    /// The same two stations, as a CSV and a JSON registry in the directory
+    fn write_registries(dir: &TempDir) -> (PathBuf, PathBuf) {
+        let csv_path = dir.join("stations.csv");
+        std::fs::write(
+            &csv_path,
+            "mmsi,station_name,latitude,longitude,owner,notes\n\
+             992501234,\"Dublin Bay, North\",53.35,-6.15,Port of Dublin,buoy\n\
+             992505678,Howth,,,,\n",
+        )
+        .unwrap();
+        let json_path = dir.join("stations.json");
+        std::fs::write(
+            &json_path,
+            r#"[{"mmsi": "992501234", "station_name": "Dublin Bay, North", "latitude": 53.35,
+                 "longitude": -6.15, "owner": "Port of Dublin"},
+                {"mmsi": "992505678", "station_name": "Howth"}]"#,
+        )
+        .unwrap();
+        (csv_path, json_path)
+    }
+
+    #[test]
+    fn test_registry_reads_csv_and_json_alike() {
+        let dir = TempDir::new("registry-formats");
+        let (csv_path, json_path) = write_registries(&dir);
+        let registry = load_mmsi_registry(&csv_path).unwrap();
+        assert_eq!(registry, load_mmsi_registry(&json_path).unwrap());
+        assert_eq!(registry[0].station_name, "Dublin Bay, North");
+        assert_eq!(registry[0].latitude, Some(53.35));
+        assert_eq!(registry[0].owner.as_deref(), Some("Port of Dublin"));
+        assert_eq!(registry[1].longitude, None);
+    }
+
+    #[test]
+    fn test_registry_rejects_other_formats() {
+        let dir = TempDir::new("registry-xlsx");
+        std::fs::write(dir.join("stations.xlsx"), "").unwrap();
+        assert!(load_mmsi_registry(&dir.join("stations.xlsx")).is_err());
+    }
+
+    #[test]
+    fn test_registry_merges_with_inline_lookups() {
+        let dir = TempDir::new("registry-merge");
+        let (csv_path, _) = write_registries(&dir);
+        let inline = vec![MMSILookup {
+            mmsi: "992505678".to_string(),
+            station_name: "Howth Harbour".to_string(),
+            ..Default::default()
+        }];
+        let merged = merge_mmsi_lookups(&inline, load_mmsi_registry(&csv_path).unwrap());
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].station_name, "Howth Harbour");
+        assert_eq!(merged[1].mmsi, "992501234");
+    }
+}
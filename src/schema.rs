@@ -0,0 +1,252 @@
+use crate::FieldType;
+use serde::Deserialize;
+use std::fmt;
+
+/// Variables that ERDDAP fills in itself for an EDDTableFromHttpGet dataset; they are never
+/// sent by the feeder.
+const ERDDAP_MANAGED_VARIABLES: &[&str] = &["timestamp", "command"];
+
+/// A variable defined by an ERDDAP dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatasetVariable {
+    pub name: String,
+    /// The ERDDAP data type name, such as "ubyte" or "String"
+    pub data_type: String,
+    pub units: Option<String>,
+}
+
+/// The variables of an ERDDAP dataset, as described by its info page.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DatasetSchema {
+    pub variables: Vec<DatasetVariable>,
+    /// Variables listed in the httpGetRequiredVariables global attribute, which every
+    /// .insert must include
+    pub required: Vec<String>,
+}
+
+/// ERDDAP's info/<datasetID>/index.json response, a table with one row per variable or
+/// attribute.
+#[derive(Deserialize)]
+struct InfoResponse {
+    table: InfoTable,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InfoTable {
+    column_names: Vec<String>,
+    rows: Vec<Vec<serde_json::Value>>,
+}
+
+/// Split a tabledap URL into the ERDDAP base URL and the dataset ID.
+fn split_dataset_url(dataset_url: &str) -> Option<(&str, &str)> {
+    let (base, dataset_id) = dataset_url
+        .trim_end_matches('/')
+        .rsplit_once("/tabledap/")?;
+    (!dataset_id.is_empty()).then_some((base, dataset_id))
+}
+
+/// The dataset ID at the end of a tabledap URL.
+pub fn dataset_id(dataset_url: &str) -> Option<&str> {
+    split_dataset_url(dataset_url).map(|(_, dataset_id)| dataset_id)
+}
+
+/// The URL of a dataset's info JSON, worked out from its tabledap URL.
+pub fn info_url(dataset_url: &str) -> Option<String> {
+    let (base, dataset_id) = split_dataset_url(dataset_url)?;
+    Some(format!("{}/info/{}/index.json", base, dataset_id))
+}
+
+impl DatasetSchema {
+    /// Read the variables out of an ERDDAP info JSON document.
+    pub fn from_info_json(json: &str) -> Result<DatasetSchema, String> {
+        let info: InfoResponse =
+            serde_json::from_str(json).map_err(|e| format!("not an ERDDAP info table: {}", e))?;
+        let column = |name: &str| {
+            info.table
+                .column_names
+                .iter()
+                .position(|c| c == name)
+                .ok_or_else(|| format!("info table has no '{}' column", name))
+        };
+        let (row_type, variable_name, attribute_name, data_type, value) = (
+            column("Row Type")?,
+            column("Variable Name")?,
+            column("Attribute Name")?,
+            column("Data Type")?,
+            column("Value")?,
+        );
+        let text = |row: &[serde_json::Value], index: usize| {
+            row.get(index)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        let mut schema = DatasetSchema::default();
+        for row in &info.table.rows {
+            let variable = text(row, variable_name);
+            match text(row, row_type).as_str() {
+                "variable" => schema.variables.push(DatasetVariable {
+                    name: variable,
+                    data_type: text(row, data_type),
+                    units: None,
+                }),
+                "attribute" => {
+                    let attribute = text(row, attribute_name);
+                    if variable == "NC_GLOBAL" && attribute == "httpGetRequiredVariables" {
+                        schema.required = text(row, value)
+                            .split(',')
+                            .map(|name| name.trim().to_string())
+                            .filter(|name| !name.is_empty())
+                            .collect();
+                    } else if attribute == "units" {
+                        if let Some(var) = schema.variables.iter_mut().find(|v| v.name == variable)
+                        {
+                            var.units = Some(text(row, value));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Compare the fields the feeder sends to a dataset with the dataset's variables.
+    pub fn check(&self, sent: &[(String, FieldType)]) -> Vec<SchemaProblem> {
+        let mut problems = vec![];
+        for (name, sent_type) in sent {
+            match self.variables.iter().find(|v| &v.name == name) {
+                None => problems.push(SchemaProblem::UndefinedField(name.clone())),
+                Some(variable) => {
+                    let Some(dataset_type) = FieldType::from_erddap(&variable.data_type) else {
+                        continue;
+                    };
+                    // ERDDAP converts ISO 8601 strings for numeric time variables itself.
+                    let is_time = variable
+                        .units
+                        .as_deref()
+                        .is_some_and(|units| units.contains(" since "));
+                    let compatible = dataset_type.can_hold(*sent_type)
+                        || (is_time && *sent_type == FieldType::String);
+                    if !compatible {
+                        problems.push(SchemaProblem::TypeMismatch {
+                            field: name.clone(),
+                            sent: *sent_type,
+                            dataset: variable.data_type.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        for required in &self.required {
+            if !ERDDAP_MANAGED_VARIABLES.contains(&required.as_str())
+                && !sent.iter().any(|(name, _)| name == required)
+            {
+                problems.push(SchemaProblem::RequiredNotSent(required.clone()));
+            }
+        }
+        problems
+    }
+}
+
+/// A difference between what the feeder sends and what an ERDDAP dataset defines
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaProblem {
+    /// The feeder sends a field the dataset has no variable for; ERDDAP will refuse the row
+    UndefinedField(String),
+    /// The dataset requires a variable the feeder never sends
+    RequiredNotSent(String),
+    /// The dataset variable's type can't hold every value the feeder sends
+    TypeMismatch {
+        field: String,
+        sent: FieldType,
+        dataset: String,
+    },
+}
+
+impl fmt::Display for SchemaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaProblem::UndefinedField(field) => write!(
+                f,
+                "the feeder sends '{}', which the dataset does not define",
+                field
+            ),
+            SchemaProblem::RequiredNotSent(field) => write!(
+                f,
+                "the dataset requires '{}', which the feeder never sends",
+                field
+            ),
+            SchemaProblem::TypeMismatch {
+                field,
+                sent,
+                dataset,
+            } => write!(
+                f,
+                "'{}' is sent as {}, but the dataset stores it as {}",
+                field, sent, dataset
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = r#"{"table": {
+        "columnNames": ["Row Type", "Variable Name", "Attribute Name", "Data Type", "Value"],
+        "columnTypes": ["String", "String", "String", "String", "String"],
+        "rows": [
+            ["attribute", "NC_GLOBAL", "httpGetRequiredVariables", "String", "mmsi,station_name,time"],
+            ["variable", "time", "", "double", ""],
+            ["attribute", "time", "units", "String", "seconds since 1970-01-01T00:00:00Z"],
+            ["variable", "mmsi", "", "uint", ""],
+            ["variable", "station_name", "", "String", ""],
+            ["variable", "wspeed", "", "byte", ""],
+            ["attribute", "wspeed", "units", "String", "knots"],
+            ["variable", "pressure", "", "short", ""],
+            ["variable", "timestamp", "", "double", ""],
+            ["variable", "author", "", "String", ""],
+            ["variable", "command", "", "byte", ""]
+        ]}}"#;
+
+    #[test]
+    fn test_check_reports_each_kind_of_problem() {
+        assert_eq!(
+            info_url("https://erddap.example.com/erddap/tabledap/ais_weather_data").unwrap(),
+            "https://erddap.example.com/erddap/info/ais_weather_data/index.json"
+        );
+        let schema = DatasetSchema::from_info_json(INFO).unwrap();
+        assert_eq!(schema.required, vec!["mmsi", "station_name", "time"]);
+        let wspeed = schema
+            .variables
+            .iter()
+            .find(|v| v.name == "wspeed")
+            .unwrap();
+        assert_eq!(wspeed.units.as_deref(), Some("knots"));
+
+        let sent = vec![
+            ("time".to_string(), FieldType::String),
+            ("mmsi".to_string(), FieldType::UInt),
+            ("wspeed".to_string(), FieldType::Byte),
+            ("pressure".to_string(), FieldType::UInt),
+            ("wspeedd".to_string(), FieldType::Byte),
+            ("author".to_string(), FieldType::String),
+        ];
+        assert_eq!(
+            schema.check(&sent),
+            vec![
+                SchemaProblem::TypeMismatch {
+                    field: "pressure".to_string(),
+                    sent: FieldType::UInt,
+                    dataset: "short".to_string()
+                },
+                SchemaProblem::UndefinedField("wspeedd".to_string()),
+                SchemaProblem::RequiredNotSent("station_name".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use crate::QueryRow;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// How many delivered entries can build up at the head of the spool file before it's
+/// rewritten without them
+pub const COMPACT_AFTER: usize = 1000;
+
+/// A row that could not be delivered to ERDDAP, waiting to be retried.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SpoolEntry {
+    /// When the row was first queued; used to report how far behind the spool is.
+    pub queued_at: DateTime<Utc>,
+    /// URL of the ERDDAP dataset, not including .insert
+    pub url: String,
+    /// The query arguments for the row, as produced by build_query_args. The author key is
+    /// only added when the row is sent, so it never hits the disk.
+    pub query_args: QueryRow,
+}
+
+/// Durable FIFO queue of rows awaiting delivery to ERDDAP.
+///
+/// Entries are appended to a JSONL file as they are queued. Delivered entries are not
+/// removed from the file; instead, a count of delivered entries is kept in a sidecar
+/// `.ack` file, so that each delivery costs a tiny write rather than a rewrite of the
+/// whole spool. Once the spool drains completely, both files are truncated; while it
+/// doesn't, the file is rewritten without the delivered entries once there are at least
+/// `COMPACT_AFTER` of them, and more of them than entries still waiting.
+#[derive(Debug)]
+pub struct Spool {
+    path: PathBuf,
+    ack_path: PathBuf,
+    /// Number of entries at the head of the spool file that have already been delivered
+    acknowledged: usize,
+    entries: VecDeque<SpoolEntry>,
+    compact_after: usize,
+}
+
+impl Spool {
+    /// Open (or create) a spool file, loading any entries left over from a previous run.
+    pub fn open(path: &Path) -> io::Result<Spool> {
+        let ack_path = ack_path_for(path);
+        let acknowledged = match std::fs::read_to_string(&ack_path) {
+            Ok(s) => s.trim().parse::<usize>().unwrap_or(0),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = VecDeque::new();
+        let mut total = 0;
+        // Whether the file holds anything but whole entries, one to a line
+        let mut damaged = false;
+        match File::open(path) {
+            Ok(file) => {
+                let mut reader = BufReader::new(file);
+                let mut line = String::new();
+                let mut number = 0;
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line)? == 0 {
+                        break;
+                    }
+                    number += 1;
+                    // A crash part way through an append can leave a truncated final line,
+                    // which the next append would run on into.
+                    if !line.ends_with('\n') {
+                        damaged = true;
+                    }
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    total += 1;
+                    if total <= acknowledged {
+                        continue;
+                    }
+                    // There's nothing to recover from an unreadable line, so skip it rather
+                    // than refusing to start.
+                    match serde_json::from_str::<SpoolEntry>(&line) {
+                        Ok(entry) => entries.push_back(entry),
+                        Err(e) => {
+                            tracing::warn!(
+                                "Skipping unreadable line {} in spool {}: {}",
+                                number,
+                                path.display(),
+                                e
+                            );
+                            damaged = true;
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut spool = Spool {
+            path: path.to_path_buf(),
+            ack_path,
+            acknowledged: acknowledged.min(total),
+            entries,
+            compact_after: COMPACT_AFTER,
+        };
+        if spool.entries.is_empty() {
+            spool.truncate()?;
+        } else if damaged {
+            // Rewrite it with only the entries that were read, so that appends start on a
+            // line of their own and the acknowledgements count lines that are entries.
+            spool.compact()?;
+        }
+        Ok(spool)
+    }
+
+    /// Append an entry to the tail of the spool, syncing it to disk before returning.
+    pub fn push(&mut self, entry: SpoolEntry) -> io::Result<()> {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        self.entries.push_back(entry);
+        Ok(())
+    }
+
+    /// Copies of up to `n` of the oldest entries in the spool.
+    pub fn front_n(&self, n: usize) -> Vec<SpoolEntry> {
+        self.entries.iter().take(n).cloned().collect()
+    }
+
+    /// Remove up to `n` of the oldest entries, recording that they have been delivered.
+    pub fn pop_front_n(&mut self, n: usize) -> io::Result<Vec<SpoolEntry>> {
+        let count = n.min(self.entries.len());
+        let removed: Vec<SpoolEntry> = self.entries.drain(..count).collect();
+        if !removed.is_empty() {
+            self.acknowledged += removed.len();
+            if self.entries.is_empty() {
+                self.truncate()?;
+            } else if self.acknowledged >= self.compact_after
+                && self.acknowledged >= self.entries.len()
+            {
+                self.compact()?;
+            } else {
+                std::fs::write(&self.ack_path, self.acknowledged.to_string())?;
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// How long the oldest entry has been waiting.
+    pub fn oldest_age(&self) -> Option<chrono::Duration> {
+        self.entries
+            .front()
+            .map(|entry| Utc::now().signed_duration_since(entry.queued_at))
+    }
+
+    /// Human-friendly summary of the spool, for logging.
+    pub fn describe(&self) -> String {
+        match self.oldest_age() {
+            Some(age) => format!(
+                "{} rows queued, oldest queued {}s ago",
+                self.len(),
+                age.num_seconds()
+            ),
+            None => "no rows queued".to_string(),
+        }
+    }
+
+    /// Rewrite the spool file with only the entries still waiting. The new file is written
+    /// alongside and renamed over the old one; the acknowledgements are forgotten first, so
+    /// a crash in between sends some rows twice rather than losing any.
+    fn compact(&mut self) -> io::Result<()> {
+        let mut compacted = self.path.as_os_str().to_owned();
+        compacted.push(".tmp");
+        let compacted = PathBuf::from(compacted);
+        let mut file = File::create(&compacted)?;
+        for entry in &self.entries {
+            let mut line = serde_json::to_string(entry)?;
+            line.push('\n');
+            file.write_all(line.as_bytes())?;
+        }
+        file.sync_data()?;
+        if self.ack_path.exists() {
+            std::fs::remove_file(&self.ack_path)?;
+        }
+        std::fs::rename(&compacted, &self.path)?;
+        self.acknowledged = 0;
+        Ok(())
+    }
+
+    /// Empty the spool file and forget any acknowledgements.
+    fn truncate(&mut self) -> io::Result<()> {
+        if self.path.exists() {
+            File::create(&self.path)?;
+        }
+        if self.ack_path.exists() {
+            std::fs::remove_file(&self.ack_path)?;
+        }
+        self.acknowledged = 0;
+        Ok(())
+    }
+}
+
+/// A sink's retry spool, shared by the tasks that send to the sink.
+///
+/// Its file is written with fsync, so async code changes it from the blocking thread pool.
+/// Sending to the sink is serialised by a separate lock, held from deciding whether rows can
+/// skip the spool until they've been sent or spooled, so they reach the sink in the order
+/// they arrived even while the spool is being drained.
+#[derive(Debug, Clone)]
+pub struct SharedSpool {
+    spool: Arc<Mutex<Spool>>,
+    sending: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl SharedSpool {
+    pub fn new(spool: Spool) -> SharedSpool {
+        SharedSpool {
+            spool: Arc::new(Mutex::new(spool)),
+            sending: Arc::default(),
+        }
+    }
+
+    /// The spool itself. Only hold it briefly in async code, and not while pushing or
+    /// popping entries.
+    pub fn lock(&self) -> MutexGuard<'_, Spool> {
+        self.spool.lock().unwrap()
+    }
+
+    /// Wait for the right to send to the sink.
+    pub async fn sending(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.sending.lock().await
+    }
+
+    /// Change the spool on the blocking thread pool.
+    pub async fn with_spool<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Spool) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let spool = self.spool.clone();
+        tokio::task::spawn_blocking(move || f(&mut spool.lock().unwrap()))
+            .await
+            .expect("retry spool update panicked")
+    }
+
+    /// Whether the two are the same spool
+    pub fn ptr_eq(&self, other: &SharedSpool) -> bool {
+        Arc::ptr_eq(&self.spool, &other.spool)
+    }
+}
+
+fn ack_path_for(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".ack");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn entry(value: &str) -> SpoolEntry {
+        SpoolEntry {
+            queued_at: Utc::now(),
+            url: "https://erddap.example.com/erddap/tabledap/test".to_string(),
+            query_args: vec![("mmsi".to_string(), value.to_string())],
+        }
+    }
+
+    /// The mmsi of each entry, which the tests use to tell them apart
+    fn values(entries: &[SpoolEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| entry.query_args[0].1.as_str())
+            .collect()
+    }
+
+    /// A spool in its own directory, holding entries with the given mmsis
+    fn spool_with(dir: &TempDir, entries: &[&str]) -> Spool {
+        let mut spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        for value in entries {
+            spool.push(entry(value)).unwrap();
+        }
+        spool
+    }
+
+    #[test]
+    fn test_spool_pops_oldest_first() {
+        let dir = TempDir::new("spool-order");
+        let mut spool = spool_with(&dir, &["1", "2", "3"]);
+        assert_eq!(values(&spool.front_n(2)), ["1", "2"]);
+        assert_eq!(values(&spool.pop_front_n(1).unwrap()), ["1"]);
+        assert_eq!(values(&spool.pop_front_n(5).unwrap()), ["2", "3"]);
+        assert!(spool.is_empty());
+    }
+
+    #[test]
+    fn test_spool_survives_reopen() {
+        let dir = TempDir::new("spool-reopen");
+        let mut spool = spool_with(&dir, &["1", "2", "3"]);
+        spool.pop_front_n(1).unwrap();
+        drop(spool);
+
+        // The delivered entry stays delivered
+        let spool = Spool::open(&dir.join("spool.jsonl")).unwrap();
+        assert_eq!(values(&spool.front_n(5)), ["2", "3"]);
+    }
+
+    #[test]
+    fn test_spool_truncates_when_drained() {
+        let dir = TempDir::new("spool-drained");
+        let path = dir.join("spool.jsonl");
+        let mut spool = spool_with(&dir, &["1", "2"]);
+        spool.pop_front_n(1).unwrap();
+        assert!(ack_path_for(&path).exists());
+        spool.pop_front_n(1).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        assert!(!ack_path_for(&path).exists());
+    }
+
+    #[test]
+    fn test_spool_skips_a_truncated_line() {
+        let dir = TempDir::new("spool-truncated");
+        let path = dir.join("spool.jsonl");
+        drop(spool_with(&dir, &["1"]));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"queued_at":"2023-10-01T12:00:00Z","url":"#)
+            .unwrap();
+
+        let mut spool = Spool::open(&path).unwrap();
+        assert_eq!(values(&spool.front_n(5)), ["1"]);
+        // Entries queued after a restart aren't lost to the damaged line
+        spool.push(entry("2")).unwrap();
+        spool.pop_front_n(1).unwrap();
+        spool.push(entry("3")).unwrap();
+        drop(spool);
+
+        let spool = Spool::open(&path).unwrap();
+        assert_eq!(values(&spool.front_n(5)), ["2", "3"]);
+    }
+
+    #[test]
+    fn test_spool_compacts_delivered_entries() {
+        let dir = TempDir::new("spool-compact");
+        let path = dir.join("spool.jsonl");
+        let mut spool = spool_with(&dir, &[]);
+        spool.compact_after = 2;
+        for value in ["1", "2", "3", "4", "5"] {
+            spool.push(entry(value)).unwrap();
+        }
+        let lines = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+        spool.pop_front_n(1).unwrap();
+        assert_eq!(lines(&path), 5);
+        // Two delivered, but three still waiting
+        spool.pop_front_n(1).unwrap();
+        assert_eq!(lines(&path), 5);
+        spool.pop_front_n(1).unwrap();
+        assert_eq!(lines(&path), 2);
+        assert!(!ack_path_for(&path).exists());
+        drop(spool);
+
+        let spool = Spool::open(&path).unwrap();
+        assert_eq!(values(&spool.front_n(5)), ["4", "5"]);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{AisStationData, Column, ColumnValue, FieldType, MessageDecoder, STATION_FIELDS};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Most observations returned by a query when it doesn't give a limit
+pub const DEFAULT_QUERY_LIMIT: usize = 1000;
+/// Most observations a query can ask for
+pub const MAX_QUERY_LIMIT: usize = 10000;
+
+/// A local history of decoded messages, kept in an SQLite database.
+///
+/// Each message type has a table named after its decoder, with `time` (as Unix seconds),
+/// `mmsi` and `station_name` columns and then a column for each field in the decoder's
+/// field list. Tables are created as messages of their type arrive, and given columns for
+/// any fields their decoder has gained since.
+pub struct Store {
+    path: PathBuf,
+    state: Mutex<StoreState>,
+}
+
+struct StoreState {
+    connection: Connection,
+    /// The tables that have been checked against their decoder's field list
+    tables: HashSet<String>,
+}
+
+/// A decoded message to add to the history
+pub struct Observation {
+    /// The decoder of the message, which names its table and lists its columns
+    pub decoder: Arc<dyn MessageDecoder>,
+    pub station: AisStationData,
+    pub station_name: String,
+    pub columns: Vec<Column>,
+}
+
+/// The filters for a query of the history, as given to the /observations route
+#[derive(Debug, Default, Deserialize)]
+pub struct ObservationQuery {
+    pub mmsi: Option<u64>,
+    /// Earliest rxtime to include, as an RFC 3339 time
+    pub from: Option<DateTime<Utc>>,
+    /// Latest rxtime to include
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl Store {
+    /// Open the database, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> rusqlite::Result<Store> {
+        let connection = Connection::open(path)?;
+        // Readers of /observations shouldn't hold up the writes.
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        Ok(Store {
+            path: path.to_path_buf(),
+            state: Mutex::new(StoreState {
+                connection,
+                tables: HashSet::new(),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add decoded messages to their types' tables, together. Only the fields in each
+    /// decoder's field list are kept; missing values are stored as NULL. This blocks on the
+    /// database, so async code should call it from spawn_blocking.
+    pub fn insert(&self, observations: &[Observation]) -> rusqlite::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let StoreState { connection, tables } = &mut *state;
+        let transaction = connection.transaction()?;
+        for observation in observations {
+            let decoder = observation.decoder.as_ref();
+            let table = decoder.name();
+            if !tables.contains(table) {
+                ensure_table(&transaction, decoder)?;
+                tables.insert(table.to_string());
+            }
+            let station = &observation.station;
+            let mut names = vec!["time", "mmsi", "station_name"];
+            let mut values = vec![
+                Value::Integer(station.rxtime.timestamp()),
+                Value::Integer(station.mmsi as i64),
+                Value::Text(observation.station_name.clone()),
+            ];
+            for column in &observation.columns {
+                if !stored_fields(decoder).any(|field| field.name == column.name) {
+                    continue;
+                }
+                names.push(&column.name);
+                values.push(match &column.value {
+                    ColumnValue::Float(value, _) if value.is_finite() => Value::Real(*value),
+                    ColumnValue::Integer(value) => Value::Integer(*value),
+                    ColumnValue::Unsigned(value) => Value::Integer(*value as i64),
+                    ColumnValue::Text(value) => Value::Text(value.clone()),
+                    ColumnValue::Float(..) | ColumnValue::Missing => Value::Null,
+                });
+            }
+            let sql = format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                quote(table),
+                names
+                    .iter()
+                    .map(|name| quote(name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                vec!["?"; names.len()].join(", ")
+            );
+            transaction
+                .prepare_cached(&sql)?
+                .execute(params_from_iter(values))?;
+        }
+        transaction.commit()
+    }
+
+    /// The observations matching a query, across every message type, oldest first. Each is
+    /// a JSON object of its table's columns, with `time` as yyyy-MM-ddTHH:mm:ssZ and the
+    /// table's name as `message_type`. Like insert, this blocks on the database.
+    pub fn query(
+        &self,
+        query: &ObservationQuery,
+    ) -> rusqlite::Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT);
+        let state = self.state.lock().unwrap();
+        let tables: Vec<String> = state
+            .connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut observations = vec![];
+        for table in tables {
+            let sql = format!(
+                "SELECT * FROM {} WHERE (?1 IS NULL OR mmsi = ?1) AND (?2 IS NULL OR time >= ?2) \
+                 AND (?3 IS NULL OR time <= ?3) ORDER BY time LIMIT ?4",
+                quote(&table)
+            );
+            let mut statement = state.connection.prepare(&sql)?;
+            let names: Vec<String> = statement
+                .column_names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect();
+            let mut rows = statement.query((
+                query.mmsi.map(|mmsi| mmsi as i64),
+                query.from.map(|from| from.timestamp()),
+                query.to.map(|to| to.timestamp()),
+                limit as i64,
+            ))?;
+            while let Some(row) = rows.next()? {
+                let mut observation = serde_json::Map::new();
+                observation.insert("message_type".to_string(), table.clone().into());
+                for (index, name) in names.iter().enumerate() {
+                    let value = match row.get_ref(index)? {
+                        ValueRef::Integer(time) if name == "time" => Utc
+                            .timestamp_opt(time, 0)
+                            .single()
+                            .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+                            .into(),
+                        ValueRef::Integer(value) => value.into(),
+                        ValueRef::Real(value) => value.into(),
+                        ValueRef::Text(value) => String::from_utf8_lossy(value).into(),
+                        ValueRef::Null | ValueRef::Blob(_) => serde_json::Value::Null,
+                    };
+                    observation.insert(name.clone(), value);
+                }
+                observations.push(observation);
+            }
+        }
+        // Each table's rows are in time order; merge them.
+        observations.sort_by(|a, b| a["time"].as_str().cmp(&b["time"].as_str()));
+        observations.truncate(limit);
+        Ok(observations)
+    }
+}
+
+/// The decoder's fields that get a column of their own. The station columns come first in
+/// every table, so a field with the same name as one of them isn't stored twice.
+fn stored_fields(decoder: &dyn MessageDecoder) -> impl Iterator<Item = &'static crate::FieldSpec> {
+    decoder
+        .fields()
+        .iter()
+        .filter(|field| !STATION_FIELDS.iter().any(|s| s.name == field.name))
+}
+
+/// Create a message type's table, or add columns for fields its decoder has gained.
+fn ensure_table(connection: &Connection, decoder: &dyn MessageDecoder) -> rusqlite::Result<()> {
+    let table = quote(decoder.name());
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (time INTEGER NOT NULL, mmsi INTEGER NOT NULL, \
+             station_name TEXT NOT NULL)",
+            table
+        ),
+        [],
+    )?;
+    connection.execute(
+        &format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} (mmsi, time)",
+            quote(&format!("{}_mmsi_time", decoder.name())),
+            table
+        ),
+        [],
+    )?;
+    let existing: HashSet<String> = connection
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map([], |row| row.get(1))?
+        .collect::<rusqlite::Result<_>>()?;
+    for field in stored_fields(decoder) {
+        if !existing.contains(field.name) {
+            connection.execute(
+                &format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table,
+                    quote(field.name),
+                    sql_type(field.data_type)
+                ),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn sql_type(data_type: FieldType) -> &'static str {
+    match data_type {
+        FieldType::Float | FieldType::Double => "REAL",
+        FieldType::String => "TEXT",
+        _ => "INTEGER",
+    }
+}
+
+/// Quote a table or column name for SQL.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::{AisMessage, AisMessageIdentifier, DecoderRegistry};
+
+    fn store_message(store: &Store, json: &str) {
+        let decoders = DecoderRegistry::with_builtin_decoders();
+        let msg: AisMessage = serde_json::from_str(json).unwrap();
+        let ami = AisMessageIdentifier::try_from(&msg).unwrap();
+        let decoder = decoders.get(&ami).unwrap();
+        let observation = Observation {
+            decoder: decoder.clone(),
+            station: AisStationData::try_from(&msg).unwrap(),
+            station_name: "Dublin Bay".to_string(),
+            columns: decoder.decode(&msg).unwrap(),
+        };
+        store.insert(&[observation]).unwrap();
+    }
+
+    /// A weather report from the test station at a time given as yyyyMMddHHmmss
+    fn store_weather(store: &Store, rxtime: &str, wspeed: u32) {
+        store_message(
+            store,
+            &format!(
+                r#"{{"rxtime": "{}", "type": 8, "dac": 200, "fid": 31,
+                    "mmsi": 992501234, "signalpower": -20.5, "lat": 53.3, "lon": -6.1,
+                    "wspeed": {}}}"#,
+                rxtime, wspeed
+            ),
+        );
+    }
+
+    fn store_position(store: &Store, rxtime: &str) {
+        store_message(
+            store,
+            &format!(
+                r#"{{"rxtime": "{}", "type": 1, "mmsi": 250001234,
+                    "signalpower": -25.0, "lat": 53.35, "lon": -6.2, "speed": 10.5,
+                    "course": 90.1, "heading": 88, "status": 0, "turn": 0}}"#,
+                rxtime
+            ),
+        );
+    }
+
+    #[test]
+    fn test_store_keeps_a_table_per_message_type() {
+        let dir = TempDir::new("store-tables");
+        let store = Store::open(&dir.join("history.sqlite")).unwrap();
+        store_weather(&store, "20231001120000", 12);
+        store_position(&store, "20231001130000");
+
+        let all = store.query(&ObservationQuery::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0]["message_type"], "imo289_met_hydro");
+        assert_eq!(all[0]["time"], "2023-10-01T12:00:00Z");
+        assert_eq!(all[0]["station_name"], "Dublin Bay");
+        assert_eq!(all[0]["wspeed"], 12);
+        assert_eq!(all[0]["lat"], 53.3);
+        assert_eq!(all[1]["message_type"], "class_a_position");
+        assert_eq!(all[1]["mmsi"], 250001234);
+    }
+
+    #[test]
+    fn test_store_survives_reopen() {
+        let dir = TempDir::new("store-reopen");
+        let path = dir.join("history.sqlite");
+        store_weather(&Store::open(&path).unwrap(), "20231001120000", 12);
+        // Reopening finds the tables as they were
+        let store = Store::open(&path).unwrap();
+        store_weather(&store, "20231002120000", 14);
+        let all = store.query(&ObservationQuery::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0]["wspeed"], 12);
+        assert_eq!(all[1]["wspeed"], 14);
+    }
+
+    #[test]
+    fn test_store_filters_by_mmsi_and_time() {
+        let dir = TempDir::new("store-filter");
+        let store = Store::open(&dir.join("history.sqlite")).unwrap();
+        store_weather(&store, "20231001120000", 12);
+        store_position(&store, "20231001190000");
+        store_weather(&store, "20231001200000", 13);
+        store_weather(&store, "20231002120000", 14);
+        store_weather(&store, "20231002130000", 15);
+
+        let night = store
+            .query(&ObservationQuery {
+                mmsi: Some(992501234),
+                from: Some("2023-10-01T18:00:00Z".parse().unwrap()),
+                to: Some("2023-10-02T12:00:00Z".parse().unwrap()),
+                limit: None,
+            })
+            .unwrap();
+        let wspeeds: Vec<_> = night.iter().map(|o| o["wspeed"].clone()).collect();
+        assert_eq!(wspeeds, [13, 14]);
+    }
+
+    #[test]
+    fn test_store_limits_across_tables_oldest_first() {
+        let dir = TempDir::new("store-limit");
+        let store = Store::open(&dir.join("history.sqlite")).unwrap();
+        store_weather(&store, "20231001120000", 12);
+        store_position(&store, "20231001110000");
+        store_weather(&store, "20231001130000", 13);
+
+        let limited = store
+            .query(&ObservationQuery {
+                limit: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(limited.len(), 2);
+        assert_eq!(limited[0]["message_type"], "class_a_position");
+        assert_eq!(limited[1]["wspeed"], 12);
+    }
+}
@@ -0,0 +1,540 @@
+use crate::{AisMessageIdentifier, AppConfig, DecoderRegistry, STATION_FIELDS};
+use crate::{DEFAULT_KEY, DEFAULT_MMSI, DEFAULT_SINK, DEFAULT_URL};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// How much a configuration problem matters. Errors stop the configuration being used;
+/// warnings are logged, and the feeder carries on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Something wrong with a configuration file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigProblem {
+    /// No MMSIs are mapped to station names
+    EmptyMmsiLookup,
+    /// The placeholder MMSI from the generated configuration is still there
+    DefaultMmsiLookup,
+    /// The placeholder ERDDAP URL from the generated configuration is still there
+    DefaultErddapUrl,
+    /// The placeholder ERDDAP key from the generated configuration is still there, either
+    /// globally or for the message type given
+    DefaultErddapKey(Option<AisMessageIdentifier>),
+    /// An MMSI that isn't a number
+    InvalidMmsi(String),
+    /// An MMSI listed more than once in mmsi_lookup
+    DuplicateMmsi(String),
+    /// Two message_config entries for the same type/dac/fid
+    DuplicateMessageConfig(AisMessageIdentifier),
+    /// Message types sent to the same dataset with different author keys; rows are only
+    /// told apart by their dataset URL, so a dataset can only have one key
+    UrlKeyClash(String),
+    /// Two fields of a message type would reach ERDDAP under the same name
+    RenameCollision {
+        message: AisMessageIdentifier,
+        name: String,
+        fields: Vec<String>,
+    },
+    /// A field in publish_fields that the message type's decoder never produces
+    UnknownPublishField {
+        message: AisMessageIdentifier,
+        field: String,
+    },
+    /// A message type the feeder has no decoder for, so its messages are always skipped
+    NoDecoder(AisMessageIdentifier),
+    /// A sink name that's empty, `default`, or has characters other than letters, digits,
+    /// `-` and `_`; the name goes into the sink's spool file name
+    InvalidSinkName(String),
+    /// Two sinks with the same name
+    DuplicateSinkName(String),
+    /// A sink with no author key
+    MissingSinkKey(String),
+    /// A sink sending to a dataset that another sink sends to as well; rows are told apart
+    /// by their dataset URL, so each sink needs its own
+    SinkUrlClash { sink: String, url: String },
+    /// An ERDDAP URL that isn't HTTPS, which ERDDAP won't accept inserts over
+    InsecureUrl(String),
+    /// An influxdb table without the named setting
+    IncompleteInfluxdb(&'static str),
+}
+
+impl ConfigProblem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ConfigProblem::UnknownPublishField { .. }
+            | ConfigProblem::NoDecoder(_)
+            | ConfigProblem::InsecureUrl(_) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigProblem::EmptyMmsiLookup => write!(f, "no MMSI lookups are defined"),
+            ConfigProblem::DefaultMmsiLookup => {
+                write!(f, "the default MMSI lookup is still present")
+            }
+            ConfigProblem::DefaultErddapUrl => write!(f, "erddap_url is still the default"),
+            ConfigProblem::DefaultErddapKey(None) => write!(f, "erddap_key is still the default"),
+            ConfigProblem::DefaultErddapKey(Some(ami)) => {
+                write!(f, "{}: erddap_key is still the default", ami)
+            }
+            ConfigProblem::InvalidMmsi(mmsi) => {
+                write!(f, "MMSI '{}' in mmsi_lookup is not a number", mmsi)
+            }
+            ConfigProblem::DuplicateMmsi(mmsi) => {
+                write!(f, "MMSI '{}' is listed more than once in mmsi_lookup", mmsi)
+            }
+            ConfigProblem::DuplicateMessageConfig(ami) => {
+                write!(f, "{} has more than one message_config entry", ami)
+            }
+            ConfigProblem::UrlKeyClash(url) => write!(
+                f,
+                "message_config sends to {} with more than one erddap_key",
+                url
+            ),
+            ConfigProblem::RenameCollision {
+                message,
+                name,
+                fields,
+            } => write!(
+                f,
+                "{}: {} would all be sent as '{}'",
+                message,
+                fields.join(", "),
+                name
+            ),
+            ConfigProblem::UnknownPublishField { message, field } => write!(
+                f,
+                "{}: publish_fields has '{}', which this message type doesn't have",
+                message, field
+            ),
+            ConfigProblem::NoDecoder(ami) => write!(
+                f,
+                "{} is accepted, but the feeder can't decode it, so it will be skipped",
+                ami
+            ),
+            ConfigProblem::InvalidSinkName(name) => write!(
+                f,
+                "sink name '{}' must be letters, digits, - and _, and not '{}'",
+                name, DEFAULT_SINK
+            ),
+            ConfigProblem::DuplicateSinkName(name) => {
+                write!(f, "more than one sink is named '{}'", name)
+            }
+            ConfigProblem::MissingSinkKey(name) => {
+                write!(f, "sink '{}' has no erddap_key or erddap_key_file", name)
+            }
+            ConfigProblem::SinkUrlClash { sink, url } => write!(
+                f,
+                "sink '{}' sends to {}, which another sink sends to as well",
+                sink, url
+            ),
+            ConfigProblem::InsecureUrl(url) => write!(
+                f,
+                "{} is not HTTPS; ERDDAP only accepts inserts over HTTPS",
+                url
+            ),
+            ConfigProblem::IncompleteInfluxdb("token") => {
+                write!(f, "influxdb has no token or token_file")
+            }
+            ConfigProblem::IncompleteInfluxdb(setting) => {
+                write!(f, "influxdb has no {}", setting)
+            }
+        }
+    }
+}
+
+/// Check a configuration, returning every problem found rather than stopping at the first.
+pub fn validate_config(config: &AppConfig, decoders: &DecoderRegistry) -> Vec<ConfigProblem> {
+    let mut problems = vec![];
+
+    if config.mmsi_lookup.is_empty() {
+        problems.push(ConfigProblem::EmptyMmsiLookup);
+    }
+    let mut seen_mmsis = HashSet::new();
+    for lookup in &config.mmsi_lookup {
+        if lookup.mmsi == DEFAULT_MMSI {
+            problems.push(ConfigProblem::DefaultMmsiLookup);
+        } else if lookup.mmsi.parse::<u64>().is_err() {
+            problems.push(ConfigProblem::InvalidMmsi(lookup.mmsi.clone()));
+        }
+        if !seen_mmsis.insert(lookup.mmsi.as_str()) {
+            problems.push(ConfigProblem::DuplicateMmsi(lookup.mmsi.clone()));
+        }
+    }
+
+    if config.erddap_url == DEFAULT_URL {
+        problems.push(ConfigProblem::DefaultErddapUrl);
+    } else if !config.erddap_url.starts_with("https://") {
+        problems.push(ConfigProblem::InsecureUrl(config.erddap_url.clone()));
+    }
+    if config.erddap_key == DEFAULT_KEY {
+        problems.push(ConfigProblem::DefaultErddapKey(None));
+    }
+
+    let mut seen_messages = HashSet::new();
+    let mut keys: HashMap<&str, &str> = HashMap::new();
+    for entry in &config.message_config {
+        let ami = AisMessageIdentifier {
+            r#type: entry.r#type,
+            dac: entry.dac,
+            fid: entry.fid,
+        };
+        if !seen_messages.insert(ami.clone()) {
+            problems.push(ConfigProblem::DuplicateMessageConfig(ami.clone()));
+        }
+        if let Some(url) = &entry.erddap_url {
+            if !url.starts_with("https://") {
+                problems.push(ConfigProblem::InsecureUrl(url.clone()));
+            }
+        }
+        if entry.erddap_key.as_deref() == Some(DEFAULT_KEY) {
+            problems.push(ConfigProblem::DefaultErddapKey(Some(ami.clone())));
+        }
+        let url = entry.erddap_url.as_deref().unwrap_or(&config.erddap_url);
+        let key = entry.erddap_key.as_deref().unwrap_or(&config.erddap_key);
+        if *keys.entry(url).or_insert(key) != key
+            && !problems.contains(&ConfigProblem::UrlKeyClash(url.to_string()))
+        {
+            problems.push(ConfigProblem::UrlKeyClash(url.to_string()));
+        }
+
+        let publish_fields = entry
+            .publish_fields
+            .as_ref()
+            .unwrap_or(&config.publish_fields);
+        let renames = entry
+            .rename_fields
+            .as_ref()
+            .unwrap_or(&config.rename_fields);
+        if decoders.get(&ami).is_none() {
+            problems.push(ConfigProblem::NoDecoder(ami.clone()));
+        }
+        check_fields(&ami, publish_fields, renames, decoders, &mut problems);
+
+        // Sinks with field lists of their own send this message type differently.
+        for sink in &config.sinks {
+            if sink.publish_fields.is_some() || sink.rename_fields.is_some() {
+                check_fields(
+                    &ami,
+                    sink.publish_fields.as_ref().unwrap_or(publish_fields),
+                    sink.rename_fields.as_ref().unwrap_or(renames),
+                    decoders,
+                    &mut problems,
+                );
+            }
+        }
+    }
+
+    let mut seen_sinks = HashSet::new();
+    let mut urls: Vec<&str> = std::iter::once(config.erddap_url.as_str())
+        .chain(
+            config
+                .message_config
+                .iter()
+                .filter_map(|entry| entry.erddap_url.as_deref()),
+        )
+        .collect();
+    for sink in &config.sinks {
+        let valid_name = !sink.name.is_empty()
+            && sink.name != DEFAULT_SINK
+            && sink
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            problems.push(ConfigProblem::InvalidSinkName(sink.name.clone()));
+        }
+        if !seen_sinks.insert(sink.name.as_str()) {
+            problems.push(ConfigProblem::DuplicateSinkName(sink.name.clone()));
+        }
+        if sink.erddap_key.is_empty() {
+            problems.push(ConfigProblem::MissingSinkKey(sink.name.clone()));
+        }
+        if urls.contains(&sink.erddap_url.as_str()) {
+            problems.push(ConfigProblem::SinkUrlClash {
+                sink: sink.name.clone(),
+                url: sink.erddap_url.clone(),
+            });
+        }
+        urls.push(&sink.erddap_url);
+        if !sink.erddap_url.starts_with("https://") {
+            problems.push(ConfigProblem::InsecureUrl(sink.erddap_url.clone()));
+        }
+    }
+
+    if let Some(influxdb) = &config.influxdb {
+        let settings = [
+            ("url", &influxdb.url),
+            ("org", &influxdb.org),
+            ("bucket", &influxdb.bucket),
+            ("token", &influxdb.token),
+        ];
+        for (setting, value) in settings {
+            if value.is_empty() {
+                problems.push(ConfigProblem::IncompleteInfluxdb(setting));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Check the fields sent for a message type: that the decoder has each field in the
+/// publish list, and that no two fields end up with the same name.
+fn check_fields(
+    ami: &AisMessageIdentifier,
+    publish_fields: &[String],
+    renames: &[(String, String)],
+    decoders: &DecoderRegistry,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    let rename_fields: HashMap<&str, &str> = renames
+        .iter()
+        .map(|(source, target)| (source.as_str(), target.as_str()))
+        .collect();
+    let decoder_fields: Vec<&str> = match decoders.get(ami) {
+        Some(decoder) => decoder.fields().iter().map(|field| field.name).collect(),
+        None => vec![],
+    };
+
+    // Without a field list, only the fields named in the configuration are known.
+    let sent: Vec<&str> = if !publish_fields.is_empty() {
+        publish_fields.iter().map(|field| field.as_str()).collect()
+    } else if !decoder_fields.is_empty() {
+        decoder_fields.clone()
+    } else {
+        renames.iter().map(|(source, _)| source.as_str()).collect()
+    };
+    if !decoder_fields.is_empty() {
+        for field in publish_fields {
+            if !decoder_fields.contains(&field.as_str()) {
+                problems.push(ConfigProblem::UnknownPublishField {
+                    message: ami.clone(),
+                    field: field.clone(),
+                });
+            }
+        }
+    }
+
+    let mut by_name: Vec<(&str, Vec<String>)> = vec![];
+    let station = STATION_FIELDS.iter().map(|field| (field.name, field.name));
+    let renamed = sent
+        .iter()
+        .map(|field| (*field, *rename_fields.get(field).unwrap_or(field)));
+    for (field, name) in station.chain(renamed) {
+        match by_name.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, fields)) => {
+                if !fields.iter().any(|f| f == field) {
+                    fields.push(field.to_string())
+                }
+            }
+            None => by_name.push((name, vec![field.to_string()])),
+        }
+    }
+    for (name, fields) in by_name {
+        if fields.len() > 1 {
+            problems.push(ConfigProblem::RenameCollision {
+                message: ami.clone(),
+                name: name.to_string(),
+                fields,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AcceptedMessage, ErddapSink, InfluxConfig, MMSILookup};
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let defaults = AppConfig::default();
+        let problems = validate_config(&defaults, &DecoderRegistry::with_builtin_decoders());
+        assert!(problems.contains(&ConfigProblem::DefaultMmsiLookup));
+        assert!(problems.contains(&ConfigProblem::DefaultErddapUrl));
+        assert!(problems.contains(&ConfigProblem::DefaultErddapKey(None)));
+
+        let weather = AisMessageIdentifier {
+            r#type: 8,
+            dac: Some(200),
+            fid: Some(31),
+        };
+        let station = |mmsi: &str| MMSILookup {
+            mmsi: mmsi.to_string(),
+            station_name: "Station".to_string(),
+            ..Default::default()
+        };
+        let mut config = AppConfig {
+            erddap_url: "http://erddap.example.com/erddap/tabledap/weather".to_string(),
+            erddap_key: "user_secret".to_string(),
+            publish_fields: vec!["lat".to_string(), "wspeed".to_string(), "wspd".to_string()],
+            rename_fields: vec![
+                ("lat".to_string(), "wind".to_string()),
+                ("wspeed".to_string(), "wind".to_string()),
+            ],
+            mmsi_lookup: vec![
+                station("992501234"),
+                station("99250x"),
+                station("992501234"),
+            ],
+            ..defaults
+        };
+        let weather_entry = config.message_config[0].clone();
+        config.message_config.push(weather_entry);
+        config.message_config.push(AcceptedMessage {
+            r#type: 5,
+            dac: None,
+            fid: None,
+            ignore_mmsi: vec![],
+            erddap_url: None,
+            erddap_key: None,
+            publish_fields: Some(vec![]),
+            rename_fields: Some(vec![("shipname".to_string(), "mmsi".to_string())]),
+        });
+        let static_data = AisMessageIdentifier {
+            r#type: 5,
+            dac: None,
+            fid: None,
+        };
+        let sink = |name: &str, url: &str| ErddapSink {
+            name: name.to_string(),
+            erddap_url: url.to_string(),
+            erddap_key: "partner_secret".to_string(),
+            erddap_key_file: None,
+            accept_invalid_certificates: false,
+            publish_fields: None,
+            rename_fields: None,
+        };
+        config.sinks = vec![
+            sink(
+                "partner",
+                "https://partner.example.com/erddap/tabledap/weather",
+            ),
+            sink(
+                "partner",
+                "https://partner.example.com/erddap/tabledap/weather",
+            ),
+            sink(
+                "default",
+                "https://other.example.com/erddap/tabledap/weather",
+            ),
+        ];
+        config.sinks[2].erddap_key = String::new();
+        config.influxdb = Some(InfluxConfig {
+            url: "http://localhost:8086".to_string(),
+            org: "harbour".to_string(),
+            bucket: String::new(),
+            token: String::new(),
+            token_file: None,
+            accept_invalid_certificates: false,
+        });
+
+        let problems = validate_config(&config, &DecoderRegistry::with_builtin_decoders());
+        let errors: Vec<&ConfigProblem> = problems
+            .iter()
+            .filter(|p| p.severity() == Severity::Error)
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                &ConfigProblem::InvalidMmsi("99250x".to_string()),
+                &ConfigProblem::DuplicateMmsi("992501234".to_string()),
+                &ConfigProblem::RenameCollision {
+                    message: weather.clone(),
+                    name: "wind".to_string(),
+                    fields: vec!["lat".to_string(), "wspeed".to_string()],
+                },
+                &ConfigProblem::DuplicateMessageConfig(weather.clone()),
+                &ConfigProblem::RenameCollision {
+                    message: weather.clone(),
+                    name: "wind".to_string(),
+                    fields: vec!["lat".to_string(), "wspeed".to_string()],
+                },
+                &ConfigProblem::RenameCollision {
+                    message: static_data.clone(),
+                    name: "mmsi".to_string(),
+                    fields: vec!["mmsi".to_string(), "shipname".to_string()],
+                },
+                &ConfigProblem::DuplicateSinkName("partner".to_string()),
+                &ConfigProblem::SinkUrlClash {
+                    sink: "partner".to_string(),
+                    url: "https://partner.example.com/erddap/tabledap/weather".to_string(),
+                },
+                &ConfigProblem::InvalidSinkName("default".to_string()),
+                &ConfigProblem::MissingSinkKey("default".to_string()),
+                &ConfigProblem::IncompleteInfluxdb("bucket"),
+                &ConfigProblem::IncompleteInfluxdb("token"),
+            ]
+        );
+        assert!(problems.contains(&ConfigProblem::InsecureUrl(config.erddap_url.clone())));
+        assert!(problems.contains(&ConfigProblem::UnknownPublishField {
+            message: weather,
+            field: "wspd".to_string()
+        }));
+        assert!(problems.contains(&ConfigProblem::NoDecoder(static_data)));
+    }
+
+    #[test]
+    fn test_validate_rejects_two_keys_for_one_dataset() {
+        let mut config = AppConfig {
+            erddap_url: "https://erddap.example.com/erddap/tabledap/weather".to_string(),
+            erddap_key: "user_secret".to_string(),
+            ..Default::default()
+        };
+        let entry = |r#type: u64, url: Option<&str>, key: Option<&str>| AcceptedMessage {
+            r#type,
+            dac: None,
+            fid: None,
+            ignore_mmsi: vec![],
+            erddap_url: url.map(|url| url.to_string()),
+            erddap_key: key.map(|key| key.to_string()),
+            publish_fields: None,
+            rename_fields: None,
+        };
+        let vessels = "https://erddap.example.com/erddap/tabledap/vessels";
+        config.message_config = vec![
+            // The same key given twice is fine
+            entry(1, Some(vessels), Some("vessels_secret")),
+            entry(2, Some(vessels), Some("vessels_secret")),
+            entry(3, None, None),
+        ];
+        let clashes = |config: &AppConfig| -> Vec<ConfigProblem> {
+            validate_config(config, &DecoderRegistry::with_builtin_decoders())
+                .into_iter()
+                .filter(|problem| matches!(problem, ConfigProblem::UrlKeyClash(_)))
+                .collect()
+        };
+        assert_eq!(clashes(&config), vec![]);
+
+        config.message_config.push(entry(5, Some(vessels), None));
+        config.message_config.push(entry(
+            18,
+            Some(&config.erddap_url.clone()),
+            Some("other_secret"),
+        ));
+        assert_eq!(
+            clashes(&config),
+            vec![
+                ConfigProblem::UrlKeyClash(vessels.to_string()),
+                ConfigProblem::UrlKeyClash(config.erddap_url.clone()),
+            ]
+        );
+    }
+}